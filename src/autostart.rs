@@ -0,0 +1,81 @@
+use std::env;
+use std::path::Path;
+use eyre::{eyre, Result, WrapErr};
+use windows_sys::Win32::Foundation::{ERROR_FILE_NOT_FOUND, ERROR_SUCCESS};
+use windows_sys::Win32::System::Registry::{RegDeleteKeyValueW, RegSetKeyValueW, HKEY_CURRENT_USER, REG_SZ};
+use crate::APP_NAME;
+use crate::win::to_wide;
+
+const RUN_KEY: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\Run";
+
+/// Registers the current executable to start at login with the given arguments, in supervisor
+/// mode so crashes are still recovered from. Returns the registered command line.
+pub fn install(args: &[String]) -> Result<String> {
+    let exe = env::current_exe().wrap_err("failed to locate executable")?;
+    let command = command_line(&exe, args);
+    let data = to_wide(&command);
+    // SAFETY: all strings are null terminated and outlive the call, the size includes the terminator
+    let result = unsafe {
+        RegSetKeyValueW(
+            HKEY_CURRENT_USER,
+            to_wide(RUN_KEY).as_ptr(),
+            to_wide(APP_NAME).as_ptr(),
+            REG_SZ,
+            data.as_ptr().cast(),
+            (data.len() * 2) as u32,
+        )
+    };
+    if result as u32 != ERROR_SUCCESS {
+        return Err(eyre!("failed to write autostart registry entry: error {result}"));
+    }
+    Ok(command)
+}
+
+/// Removes the autostart entry, returns whether there was one
+pub fn uninstall() -> Result<bool> {
+    // SAFETY: all strings are null terminated and outlive the call
+    let result = unsafe {
+        RegDeleteKeyValueW(HKEY_CURRENT_USER, to_wide(RUN_KEY).as_ptr(), to_wide(APP_NAME).as_ptr())
+    };
+    match result as u32 {
+        ERROR_SUCCESS => Ok(true),
+        ERROR_FILE_NOT_FOUND => Ok(false),
+        _ => Err(eyre!("failed to delete autostart registry entry: error {result}")),
+    }
+}
+
+fn command_line(exe: &Path, args: &[String]) -> String {
+    let mut command = format!("\"{}\"", exe.display());
+    for arg in args {
+        command.push(' ');
+        command.push_str(&quote_arg(arg));
+    }
+    command
+}
+
+/// Quotes an argument so `CommandLineToArgvW` splits it back out unchanged
+pub fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| matches!(c, ' ' | '\t' | '\n' | '\x0b' | '"')) {
+        return arg.to_owned();
+    }
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                continue;
+            }
+            // Backslashes only escape when a quote follows, so they are doubled and the quote is
+            // escaped as well
+            '"' => quoted.push_str(&"\\".repeat(backslashes * 2 + 1)),
+            _ => quoted.push_str(&"\\".repeat(backslashes)),
+        }
+        backslashes = 0;
+        quoted.push(c);
+    }
+    // The closing quote follows any trailing backslashes
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
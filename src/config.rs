@@ -0,0 +1,1309 @@
+use std::{env, fmt, fs};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use eyre::{ensure, eyre, Report, Result, WrapErr};
+use log::LevelFilter;
+use serde::Deserialize;
+use win32_coreaudio::{DataFlow, DeviceRole};
+use crate::vm::{nearly_equal, DEFAULT_FLOAT_EPSILON, MAX_GAIN, MIN_GAIN};
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+/// Stands for the index of the target in `gain_parameter` and `mute_parameter`
+const PARAMETER_INDEX_PLACEHOLDER: &str = "{index}";
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// VoiceMeeter strips and buses that follow the Windows volume
+    pub targets: Vec<Target>,
+    /// Strips and buses that follow the volume of the left channel instead of the master volume
+    pub left_targets: Vec<Target>,
+    /// Strips and buses that follow the volume of the right channel instead of the master volume
+    pub right_targets: Vec<Target>,
+    /// Gain applied when the Windows volume is at 0%, in `gain_unit`
+    pub min_gain: f32,
+    /// Gain applied when the Windows volume is at 100%, in `gain_unit`
+    pub max_gain: f32,
+    /// Whether `min_gain` and `max_gain` are in dB or fader positions
+    pub gain_unit: GainUnit,
+    /// Hard limit for the gain written to any target in dB, applied after the per-target offsets
+    pub gain_cap: f32,
+    /// Use the master volume in dB reported by Windows as the gain instead of mapping the scalar
+    /// onto the gain range
+    pub use_system_db: bool,
+    /// How the Windows volume is mapped onto the gain range
+    pub curve: Curve,
+    /// Points to interpolate between instead of using `curve`
+    pub curve_file: Option<CurveTable>,
+    /// Map 0% onto `max_gain` and 100% onto `min_gain` instead, e.g. to duck a strip
+    pub invert: bool,
+    /// One of error, warn, info, debug or trace
+    pub log_level: LevelFilter,
+    /// How log lines are written
+    pub log_format: LogFormat,
+    /// How long to wait for further volume changes before applying the latest one, in ms
+    pub debounce_ms: u64,
+    /// Gain changes smaller than this many dB are not written, mute changes always are
+    pub min_change: f32,
+    /// Round the gain to multiples of this many dB, 0 to disable
+    pub gain_step: f32,
+    /// How long to wait after switching to another device before applying its volume, in ms
+    pub device_settle_ms: u64,
+    /// Also mirror gain changes made in VoiceMeeter back onto the Windows volume
+    pub bidirectional: bool,
+    /// Sync from the device whose name contains this instead of the default device
+    pub device: Option<String>,
+    /// Sync from the device whose name contains this while neither `device` nor the default device
+    /// is available
+    pub fallback_device: Option<String>,
+    /// Only ever sync from the device with this endpoint ID, no matter which device is the default
+    pub pin_device_id: Option<String>,
+    /// Whether to sync from an output or an input device
+    pub flow: Flow,
+    /// Which of the default devices is synced from
+    pub role: Role,
+    /// Unmute the targets when Windows is muted and vice versa
+    pub invert_mute: bool,
+    /// Treat a volume of 0% as muted
+    pub zero_mute: bool,
+    /// Percent the volume has to rise above 0% before a zero volume mute is lifted again
+    pub zero_mute_hysteresis: f32,
+    /// Mute when the gain reaches `min_gain`
+    pub floor_mute: bool,
+    /// Mute whenever the gain is at or below this many dB
+    pub mute_below: Option<f32>,
+    /// How many dB the gain has to rise above `mute_below` before unmuting again
+    pub mute_below_hysteresis: f32,
+    /// Only sync the gain, leaving the mute state of the targets alone
+    pub gain_only: bool,
+    /// Only sync the mute state, leaving the gain of the targets alone
+    pub mute_only: bool,
+    /// Mute the targets before writing the gain at startup and only then apply the synced mute
+    pub safe_start: bool,
+    /// Macro button that is switched on whenever the targets are muted
+    pub mute_macro: Option<usize>,
+    /// Only write to the targets while this VoiceMeeter parameter has the given value
+    pub sync_when: Option<Gate>,
+    /// Gain parameter written instead of `Gain`, with `{index}` standing for the index of the
+    /// target, e.g. `Strip[{index}].GainLayer[2]`
+    pub gain_parameter: Option<String>,
+    /// Mute parameter written instead of `Mute`, with the same `{index}` placeholder
+    pub mute_parameter: Option<String>,
+    /// Other float parameters that follow the Windows volume, each mapped onto its own range
+    pub mappings: Vec<Mapping>,
+    /// Read the gains back after writing them and write them again if they didn't stick
+    pub verify_writes: bool,
+    /// Gains closer than this many dB count as the same, e.g. when skipping unchanged writes or
+    /// checking the gain that was read back
+    pub float_epsilon: f32,
+    /// Log parameter writes instead of sending them to VoiceMeeter
+    pub dry_run: bool,
+    /// Log every volume and device event with a sequence number, along with the write it led to
+    pub verbose_events: bool,
+    /// Move the gain to a new value over this many ms instead of jumping to it
+    pub ramp_ms: u64,
+    /// Move the gain by at most this many dB per second, 0 to disable
+    pub max_slew: f32,
+    /// Let VoiceMeeter fade the gain to a new value over this many ms instead of writing it directly
+    pub fade_ms: u64,
+    /// How often to check whether VoiceMeeter is still running, 0 to disable
+    pub liveness_check_secs: u64,
+    /// Register the callbacks again after this many seconds without events from Windows, 0 to disable
+    pub watchdog_secs: u64,
+    /// How long to wait for VoiceMeeter to start before syncing, 0 to not wait
+    pub wait_for_voicemeeter_secs: u64,
+    /// Delay before the supervisor boots the crashed program again, in seconds
+    pub restart_delay_secs: u64,
+    /// Upper limit for the restart delay, which doubles with every crash in a row
+    pub restart_delay_max_secs: u64,
+    /// Give up after this many crashes in a row, 0 to never give up
+    pub max_restarts: u32,
+    /// Sync in this process instead of a supervised child, e.g. to attach a debugger
+    pub no_managed: bool,
+    /// How many times a failed VoiceMeeter write is retried with a fresh connection
+    pub retries: u32,
+    /// Show a tray icon with the sync status
+    pub tray: bool,
+    /// Show desktop notifications when recovering from crashes and VoiceMeeter restarts
+    pub notify: bool,
+    /// Windows volumes within this many percent of 0% or 100% are treated as exactly 0% or 100%
+    pub deadzone: f32,
+    /// Accept control commands on the named pipe `\\.\pipe\<name>`
+    pub control_pipe: Option<String>,
+    /// Serve the sync status as JSON on this localhost port
+    pub http_port: Option<u16>,
+    /// Keep the sync status as JSON in this file
+    pub status_file: Option<PathBuf>,
+    /// Also sync volumes sent to `http://<listen>/volume`, e.g. from another machine
+    pub listen: Option<String>,
+    /// Global hotkey that pauses and resumes syncing
+    pub pause_hotkey: Option<Hotkey>,
+    /// Number of strips and buses shown by `--list-parameters`, defaults to all that exist
+    pub list_count: Option<usize>,
+    /// Devices synced to their own targets, each independently of the others. If any are set,
+    /// only these are synced and the options above are the defaults for them.
+    pub pairs: Vec<Pair>,
+    /// Named sets of options, one of which can be picked with `profile`
+    pub profiles: BTreeMap<String, Profile>,
+    /// Profile applied on top of the config file
+    pub profile: Option<String>,
+    /// Index of the pair in the config file this config was derived from
+    #[serde(skip)]
+    pub pair: Option<usize>,
+    /// Whether `min_gain` or `max_gain` was configured anywhere, otherwise the range can be picked
+    /// for the running VoiceMeeter edition
+    #[serde(skip)]
+    pub gain_range_set: bool,
+    /// One-off command to run instead of syncing, only settable from the command line
+    #[serde(skip)]
+    pub command: Option<Command>,
+    /// Config file this was loaded from, which may not exist
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// Whether the config file existed when this was loaded
+    #[serde(skip)]
+    file_found: bool,
+    /// Command line arguments this was loaded with, kept around to reload the config
+    #[serde(skip)]
+    args: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            targets: vec![Target { channel: SyncTarget::Strip(3), label: None, label_fallback: None, offset_db: 0.0 }],
+            left_targets: Vec::new(),
+            right_targets: Vec::new(),
+            min_gain: -30.0,
+            max_gain: 12.0,
+            gain_unit: GainUnit::Db,
+            gain_cap: 12.0,
+            use_system_db: false,
+            curve: Curve::Linear,
+            curve_file: None,
+            invert: false,
+            log_level: LevelFilter::Info,
+            log_format: LogFormat::Text,
+            debounce_ms: 0,
+            min_change: 0.0,
+            gain_step: 0.0,
+            device_settle_ms: 0,
+            bidirectional: false,
+            device: None,
+            fallback_device: None,
+            pin_device_id: None,
+            flow: Flow::Render,
+            role: Role::Multimedia,
+            invert_mute: false,
+            zero_mute: true,
+            zero_mute_hysteresis: 0.0,
+            floor_mute: false,
+            mute_below: None,
+            mute_below_hysteresis: 1.0,
+            gain_only: false,
+            mute_only: false,
+            safe_start: false,
+            mute_macro: None,
+            sync_when: None,
+            gain_parameter: None,
+            mute_parameter: None,
+            mappings: Vec::new(),
+            verify_writes: false,
+            float_epsilon: DEFAULT_FLOAT_EPSILON,
+            dry_run: false,
+            verbose_events: false,
+            ramp_ms: 0,
+            max_slew: 0.0,
+            fade_ms: 0,
+            liveness_check_secs: 5,
+            watchdog_secs: 0,
+            wait_for_voicemeeter_secs: 0,
+            restart_delay_secs: 5,
+            restart_delay_max_secs: 300,
+            max_restarts: 0,
+            no_managed: false,
+            retries: 1,
+            tray: true,
+            notify: false,
+            deadzone: 0.0,
+            control_pipe: None,
+            http_port: None,
+            status_file: None,
+            listen: None,
+            pause_hotkey: None,
+            list_count: None,
+            pairs: Vec::new(),
+            profiles: BTreeMap::new(),
+            profile: None,
+            pair: None,
+            gain_range_set: false,
+            command: None,
+            path: PathBuf::new(),
+            file_found: false,
+            args: Vec::new(),
+        }
+    }
+}
+
+/// A device and the targets that follow it, only settable from the config file
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pair {
+    /// Defaults to `device` of the main config
+    pub device: Option<String>,
+    pub targets: Vec<Target>,
+    /// Defaults to `min_gain` of the main config
+    pub min_gain: Option<f32>,
+    /// Defaults to `max_gain` of the main config
+    pub max_gain: Option<f32>,
+}
+
+/// Options to switch between with `--profile`, only settable from the config file. Anything not set
+/// is left as configured outside of the profile.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub device: Option<String>,
+    pub targets: Option<Vec<Target>>,
+    pub min_gain: Option<f32>,
+    pub max_gain: Option<f32>,
+    pub curve: Option<Curve>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    InstallAutostart,
+    UninstallAutostart,
+    ListDevices,
+    ListParameters,
+    /// Apply the current volume once and exit
+    Once,
+    InstallService,
+    UninstallService,
+    /// Sync as a Windows service, only used by the service manager
+    RunAsService,
+    /// Print the config resulting from the defaults, config file and arguments
+    PrintConfig,
+    /// Check that the targets exist and can be written to
+    SelfTest,
+    /// Write this gain in dB to the targets and exit, set with `--set-gain <db>`
+    SetGain(f32),
+    /// Print every Windows volume change without connecting to VoiceMeeter
+    Monitor,
+}
+
+impl Command {
+    const FLAGS: &'static [(&'static str, Command)] = &[
+        ("--install-autostart", Command::InstallAutostart),
+        ("--uninstall-autostart", Command::UninstallAutostart),
+        ("--list-devices", Command::ListDevices),
+        ("--list-parameters", Command::ListParameters),
+        ("--once", Command::Once),
+        ("--install-service", Command::InstallService),
+        ("--uninstall-service", Command::UninstallService),
+        ("--run-as-service", Command::RunAsService),
+        ("--print-config", Command::PrintConfig),
+        ("--self-test", Command::SelfTest),
+        ("--monitor", Command::Monitor),
+    ];
+
+    fn from_flag(flag: &str) -> Option<Self> {
+        Self::FLAGS.iter()
+            .find(|(name, _)| *name == flag)
+            .map(|(_, command)| *command)
+    }
+
+    pub fn is_flag(flag: &str) -> bool {
+        Self::from_flag(flag).is_some()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Flow {
+    Render,
+    Capture,
+}
+
+impl Flow {
+    pub fn data_flow(&self) -> DataFlow {
+        match self {
+            Flow::Render => DataFlow::Render,
+            Flow::Capture => DataFlow::Capture,
+        }
+    }
+}
+
+impl FromStr for Flow {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "render" => Ok(Flow::Render),
+            "capture" => Ok(Flow::Capture),
+            _ => Err(eyre!("unknown flow {s}, expected render or capture")),
+        }
+    }
+}
+
+/// Windows keeps a default device per role, apps pick the one matching what they do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Role {
+    Console,
+    Multimedia,
+    Communications,
+}
+
+impl Role {
+    pub fn device_role(&self) -> DeviceRole {
+        match self {
+            Role::Console => DeviceRole::Console,
+            Role::Multimedia => DeviceRole::Multimedia,
+            Role::Communications => DeviceRole::Communications,
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "console" => Ok(Role::Console),
+            "multimedia" => Ok(Role::Multimedia),
+            "communications" => Ok(Role::Communications),
+            _ => Err(eyre!("unknown role {s}, expected multimedia, communications or console")),
+        }
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Curve {
+    Linear,
+    Log,
+    Squared,
+}
+
+impl FromStr for Curve {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "linear" => Ok(Curve::Linear),
+            "log" => Ok(Curve::Log),
+            "squared" => Ok(Curve::Squared),
+            _ => Err(eyre!("unknown curve {s}, expected linear, log or squared")),
+        }
+    }
+}
+
+/// Custom curve loaded from a file of `(volume, gain)` points, the gain is interpolated linearly
+/// between them. Parsing a path loads and checks the file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct CurveTable {
+    /// Sorted by volume, with the gain never going down
+    points: Vec<(f32, f32)>,
+}
+
+impl CurveTable {
+    /// Gain in dB at a Windows volume scalar, volumes outside of the table get the gain of the
+    /// closest end
+    pub fn gain(&self, volume: f32) -> f32 {
+        interpolate(&self.points, volume)
+    }
+
+    /// Lowest and highest gain in dB of the table
+    pub fn gain_range(&self) -> (f32, f32) {
+        (self.points[0].1, self.points[self.points.len() - 1].1)
+    }
+
+    /// Inverse of `gain`
+    pub fn volume(&self, gain: f32) -> f32 {
+        let inverted: Vec<(f32, f32)> = self.points.iter().map(|&(volume, gain)| (gain, volume)).collect();
+        interpolate(&inverted, gain)
+    }
+}
+
+/// Linear interpolation between sorted points, clamped to the first and last one
+fn interpolate(points: &[(f32, f32)], x: f32) -> f32 {
+    let (first, last) = (points[0], points[points.len() - 1]);
+    if x <= first.0 {
+        return first.1;
+    }
+    for window in points.windows(2) {
+        let ((x0, y0), (x1, y1)) = (window[0], window[1]);
+        // Flat parts of the inverted table have no single answer, the lowest one is used
+        if x <= x1 {
+            return if x1 == x0 { y0 } else { y0 + (y1 - y0) * (x - x0) / (x1 - x0) };
+        }
+    }
+    last.1
+}
+
+impl FromStr for CurveTable {
+    type Err = Report;
+
+    /// Loads `[[volume, gain], ...]` from `.json` files and `volume,gain` lines from any other
+    fn from_str(s: &str) -> Result<Self> {
+        let path = PathBuf::from(s);
+        let contents = fs::read_to_string(&path)
+            .wrap_err_with(|| format!("failed to read curve file {}", path.display()))?;
+        let is_json = matches!(path.extension(), Some(extension) if extension.eq_ignore_ascii_case("json"));
+        let points = if is_json {
+            serde_json::from_str(&contents).wrap_err("expected a list of [volume, gain] points")
+        } else {
+            parse_csv_points(&contents)
+        }.wrap_err_with(|| format!("failed to parse curve file {}", path.display()))?;
+        check_points(&points).wrap_err_with(|| format!("invalid curve file {}", path.display()))?;
+        Ok(Self { points })
+    }
+}
+
+impl TryFrom<String> for CurveTable {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+/// One `volume,gain` point per line, empty lines and lines starting with `#` are skipped
+fn parse_csv_points(contents: &str) -> Result<Vec<(f32, f32)>> {
+    contents.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| match line.split_once(',') {
+            Some((volume, gain)) => Ok((
+                volume.trim().parse().wrap_err_with(|| format!("invalid volume on line {number}"))?,
+                gain.trim().parse().wrap_err_with(|| format!("invalid gain on line {number}"))?,
+            )),
+            None => Err(eyre!("expected volume,gain on line {number}")),
+        })
+        .collect()
+}
+
+fn check_points(points: &[(f32, f32)]) -> Result<()> {
+    ensure!(points.len() >= 2, "at least two points are required");
+    for &(volume, gain) in points {
+        ensure!((0.0..=1.0).contains(&volume), "volume {volume} must be between 0 and 1");
+        ensure!(gain.is_finite(), "gain {gain} must be finite");
+    }
+    for window in points.windows(2) {
+        let ((volume0, gain0), (volume1, gain1)) = (window[0], window[1]);
+        ensure!(volume1 > volume0, "volumes must be increasing, {volume1} follows {volume0}");
+        // Otherwise the gain can't be mapped back onto a volume with --bidirectional
+        ensure!(gain1 >= gain0, "gains must not decrease, {gain1} follows {gain0}");
+    }
+    Ok(())
+}
+
+/// Format of the log output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum LogFormat {
+    /// Colored lines meant to be read by humans
+    Text,
+    /// One JSON object per line with the timestamp, level, target and message
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(eyre!("unknown log format {s}, expected text or json")),
+        }
+    }
+}
+
+impl TryFrom<String> for LogFormat {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+/// Unit of the configured gain range
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum GainUnit {
+    /// Decibels, as used by VoiceMeeter
+    Db,
+    /// Fader position from 0 (bottom, -60 dB) to 100 (top, +12 dB)
+    Fader,
+}
+
+impl FromStr for GainUnit {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "db" => Ok(GainUnit::Db),
+            "fader" => Ok(GainUnit::Fader),
+            _ => Err(eyre!("unknown gain unit {s}, expected db or fader")),
+        }
+    }
+}
+
+impl TryFrom<String> for GainUnit {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+/// A strip or bus that follows the Windows volume, with a gain offset relative to the other targets
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Target {
+    /// For targets picked by label this is only meaningful once the label has been resolved
+    pub channel: SyncTarget,
+    /// Label of the strip to sync, its index is looked up whenever we connect to VoiceMeeter
+    pub label: Option<String>,
+    /// Strip index to use if the labels can't be read from VoiceMeeter
+    pub label_fallback: Option<usize>,
+    pub offset_db: f32,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.label {
+            Some(label) => write!(f, "Strip \"{label}\""),
+            None => self.channel.fmt(f),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = Report;
+
+    /// Parses `<strip|bus>:<index>[:<offset>]` or `label:<label>[@<fallback index>][:<offset>]`
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(3, ':');
+        let kind = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let value = parts.next()
+            .ok_or_else(|| eyre!("expected <strip|bus>:<index>[:<offset>] or label:<label>[@<index>][:<offset>]"))?;
+        let offset_db = parts.next()
+            .map(str::parse::<f32>)
+            .transpose()
+            .wrap_err("invalid offset")?
+            .unwrap_or(0.0);
+        if kind == "label" {
+            // Labels may contain @ as well, only a trailing number is taken as the fallback
+            let (label, label_fallback) = match value.rsplit_once('@') {
+                Some((label, index)) if !index.is_empty() && index.bytes().all(|c| c.is_ascii_digit()) => {
+                    (label, Some(index.parse().wrap_err("invalid fallback index")?))
+                }
+                _ => (value, None),
+            };
+            ensure!(!label.is_empty(), "label must not be empty");
+            return Ok(Self {
+                channel: SyncTarget::Strip(label_fallback.unwrap_or_default()),
+                label: Some(label.to_owned()),
+                label_fallback,
+                offset_db,
+            });
+        }
+        let index = value.parse().wrap_err("invalid index")?;
+        let channel = match kind.as_str() {
+            "strip" => SyncTarget::Strip(index),
+            "bus" => SyncTarget::Bus(index),
+            _ => return Err(eyre!("unknown target kind {kind}, expected strip, bus or label")),
+        };
+        Ok(Self { channel, label: None, label_fallback: None, offset_db })
+    }
+}
+
+impl TryFrom<String> for Target {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+/// Which volume a target added on the command line follows
+#[derive(Clone, Copy)]
+enum TargetKind {
+    Master,
+    Left,
+    Right,
+}
+
+/// A VoiceMeeter parameter and the value it needs to have, e.g. `Strip[3].B1=1`
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Gate {
+    pub param: String,
+    pub value: f32,
+}
+
+impl Gate {
+    /// Whether `value` read from the parameter is the expected one, within `epsilon`
+    pub fn is_met(&self, value: f32, epsilon: f32) -> bool {
+        nearly_equal(value, self.value, epsilon)
+    }
+}
+
+impl fmt::Display for Gate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.param, self.value)
+    }
+}
+
+impl FromStr for Gate {
+    type Err = Report;
+
+    /// Parses `<parameter>=<value>`
+    fn from_str(s: &str) -> Result<Self> {
+        let (param, value) = s.split_once('=')
+            .ok_or_else(|| eyre!("expected <parameter>=<value>, e.g. Strip[3].B1=1"))?;
+        let param = param.trim();
+        ensure!(!param.is_empty(), "parameter must not be empty");
+        let value = value.trim().parse().wrap_err("invalid value")?;
+        Ok(Self { param: param.to_owned(), value })
+    }
+}
+
+impl TryFrom<String> for Gate {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+/// A VoiceMeeter float parameter that follows the Windows volume, e.g. `Strip[3].EQGain1:-12:12`
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Mapping {
+    pub param: String,
+    /// Value written at 0% Windows volume
+    pub min: f32,
+    /// Value written at 100% Windows volume
+    pub max: f32,
+}
+
+impl Mapping {
+    /// Value of the parameter at a Windows volume scalar (0.0 - 1.0)
+    pub fn value(&self, volume: f32) -> f32 {
+        self.min + (self.max - self.min) * volume
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.param, self.min, self.max)
+    }
+}
+
+impl FromStr for Mapping {
+    type Err = Report;
+
+    /// Parses `<parameter>:<min>:<max>`
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.rsplitn(3, ':');
+        let (max, min, param) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(max), Some(min), Some(param)) => (max, min, param.trim()),
+            _ => return Err(eyre!("expected <parameter>:<min>:<max>, e.g. Strip[3].EQGain1:-12:12")),
+        };
+        ensure!(!param.is_empty(), "parameter must not be empty");
+        let min: f32 = min.trim().parse().wrap_err("invalid min")?;
+        let max: f32 = max.trim().parse().wrap_err("invalid max")?;
+        ensure!(min.is_finite() && max.is_finite(), "min and max must be finite");
+        Ok(Self { param: param.to_owned(), min, max })
+    }
+}
+
+impl TryFrom<String> for Mapping {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+/// Key combination that can be registered as a global hotkey, e.g. `ctrl+alt+p`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Hotkey {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub win: bool,
+    /// Windows virtual key code of the key pressed along with the modifiers
+    pub key: u32,
+}
+
+/// Virtual key code of F1, F2 to F24 follow it
+const VK_F1: u32 = 0x70;
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let modifiers = [(self.ctrl, "ctrl"), (self.alt, "alt"), (self.shift, "shift"), (self.win, "win")];
+        for (pressed, name) in modifiers {
+            if pressed {
+                write!(f, "{name}+")?;
+            }
+        }
+        match self.key {
+            key if (VK_F1..VK_F1 + 24).contains(&key) => write!(f, "f{}", key - VK_F1 + 1),
+            // Letters and digits have their ASCII code as virtual key code
+            key => write!(f, "{}", char::from_u32(key).unwrap_or('?').to_ascii_lowercase()),
+        }
+    }
+}
+
+impl FromStr for Hotkey {
+    type Err = Report;
+
+    /// Parses modifiers and a key joined by `+`, e.g. `ctrl+shift+f9`
+    fn from_str(s: &str) -> Result<Self> {
+        let mut hotkey = Self { ctrl: false, alt: false, shift: false, win: false, key: 0 };
+        let mut parts: Vec<String> = s.split('+').map(|part| part.trim().to_ascii_lowercase()).collect();
+        let key = parts.pop().unwrap_or_default();
+        for part in parts {
+            match part.as_str() {
+                "ctrl" | "control" => hotkey.ctrl = true,
+                "alt" => hotkey.alt = true,
+                "shift" => hotkey.shift = true,
+                "win" => hotkey.win = true,
+                _ => return Err(eyre!("unknown modifier {part}, expected ctrl, alt, shift or win")),
+            }
+        }
+        ensure!(hotkey.ctrl || hotkey.alt || hotkey.shift || hotkey.win, "at least one modifier is required");
+        hotkey.key = match key.strip_prefix('f').and_then(|number| number.parse::<u32>().ok()) {
+            Some(number) if (1..=24).contains(&number) => VK_F1 + number - 1,
+            _ => match key.as_bytes() {
+                [key] if key.is_ascii_alphanumeric() => u32::from(key.to_ascii_uppercase()),
+                _ => return Err(eyre!("unknown key {key}, expected a letter, a digit or f1 to f24")),
+            },
+        };
+        Ok(hotkey)
+    }
+}
+
+impl TryFrom<String> for Hotkey {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncTarget {
+    Strip(usize),
+    Bus(usize),
+}
+
+impl SyncTarget {
+    /// Builds the full VoiceMeeter parameter name, e.g. `Strip[3].Gain`
+    pub fn parameter(&self, name: &str) -> String {
+        format!("{self}.{name}")
+    }
+
+    pub fn index(&self) -> usize {
+        match self {
+            SyncTarget::Strip(index) | SyncTarget::Bus(index) => *index,
+        }
+    }
+}
+
+impl fmt::Display for SyncTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncTarget::Strip(index) => write!(f, "Strip[{index}]"),
+            SyncTarget::Bus(index) => write!(f, "Bus[{index}]"),
+        }
+    }
+}
+
+impl TryFrom<String> for Curve {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl TryFrom<String> for Flow {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl Config {
+    /// Loads the config file (from `--config <path>` or next to the executable) and applies the
+    /// profile, environment variables and command line arguments on top of it, in that order.
+    ///
+    /// The path of the config file is kept in `path`, the file may not exist.
+    pub fn load(args: &[String]) -> Result<Self> {
+        let path = match args.iter().position(|arg| arg == "--config") {
+            Some(i) => args.get(i + 1)
+                .map(PathBuf::from)
+                .ok_or_else(|| eyre!("missing value for --config"))?,
+            None => env::current_exe()
+                .wrap_err("failed to locate executable")?
+                .with_file_name(CONFIG_FILE_NAME),
+        };
+        let file_found = path.exists();
+        let mut config = if file_found {
+            Self::from_file(&path)?
+        } else {
+            Self::default()
+        };
+        if let Some(i) = args.iter().position(|arg| arg == "--profile") {
+            config.profile = Some(args.get(i + 1).cloned().ok_or_else(|| eyre!("missing value for --profile"))?);
+        }
+        config.apply_profile()?;
+        config.apply_env()?;
+        config.apply_args(args.iter().cloned())?;
+        // The default range is in dB, fader positions use the whole fader unless told otherwise
+        if config.gain_unit == GainUnit::Fader && !config.gain_range_set {
+            config.min_gain = 0.0;
+            config.max_gain = 100.0;
+        }
+        config.validate()?;
+        config.path = path;
+        config.file_found = file_found;
+        config.args = args.to_vec();
+        Ok(config)
+    }
+
+    /// Targets following the master volume, followed by the ones following a channel
+    pub fn all_targets(&self) -> impl Iterator<Item = &Target> {
+        self.targets.iter().chain(&self.left_targets).chain(&self.right_targets)
+    }
+
+    /// Name of the gain parameter written to `target`, e.g. `Strip[3].Gain`
+    pub fn gain_param(&self, target: SyncTarget) -> String {
+        parameter_name(self.gain_parameter.as_deref(), target, "Gain")
+    }
+
+    /// Name of the mute parameter written to `target`, e.g. `Strip[3].Mute`
+    pub fn mute_param(&self, target: SyncTarget) -> String {
+        parameter_name(self.mute_parameter.as_deref(), target, "Mute")
+    }
+
+    /// Whether any target follows the volume of a single channel
+    pub fn has_channel_targets(&self) -> bool {
+        !self.left_targets.is_empty() || !self.right_targets.is_empty()
+    }
+
+    /// Loads the config again from the same file and arguments, e.g. after the file changed.
+    /// A `profile` replaces the one that was selected, including for later reloads.
+    pub fn reload(&self, profile: Option<&str>) -> Result<Self> {
+        let mut args = self.args.clone();
+        if let Some(profile) = profile {
+            if let Some(i) = args.iter().position(|arg| arg == "--profile") {
+                args.drain(i..(i + 2).min(args.len()));
+            }
+            args.extend(["--profile".to_owned(), profile.to_owned()]);
+        }
+        // Loading would fall back to the defaults, keep the current config until the file is back
+        ensure!(
+            !self.file_found || self.path.exists(),
+            "config file {} is missing",
+            self.path.display()
+        );
+        let config = Self::load(&args)?;
+        match self.pair {
+            Some(index) => config.pair_configs().into_iter().nth(index)
+                .ok_or_else(|| eyre!("pair {index} was removed, removing pairs needs a restart")),
+            None => {
+                ensure!(config.pairs.is_empty(), "adding pairs needs a restart");
+                Ok(config)
+            }
+        }
+    }
+
+    /// Config for syncing each of the `pairs` on its own
+    pub fn pair_configs(&self) -> Vec<Self> {
+        self.pairs.iter().enumerate()
+            .map(|(index, pair)| {
+                let mut config = self.clone();
+                config.pairs = Vec::new();
+                config.pair = Some(index);
+                config.device = pair.device.clone().or_else(|| self.device.clone());
+                config.targets = pair.targets.clone();
+                config.left_targets = Vec::new();
+                config.right_targets = Vec::new();
+                config.min_gain = pair.min_gain.unwrap_or(self.min_gain);
+                config.max_gain = pair.max_gain.unwrap_or(self.max_gain);
+                config.gain_range_set |= pair.min_gain.is_some() || pair.max_gain.is_some();
+                // There can only be one of each per process, they serve the first pair
+                if index > 0 {
+                    config.tray = false;
+                    config.http_port = None;
+                    config.status_file = None;
+                    config.listen = None;
+                    config.pause_hotkey = None;
+                    config.control_pipe = None;
+                }
+                config
+            })
+            .collect()
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read config file {}", path.display()))?;
+        let value: toml::Value = toml::from_str(&contents)
+            .wrap_err_with(|| format!("failed to parse config file {}", path.display()))?;
+        // Defaults can't be told apart from configured values after deserializing
+        let gain_range_set = ["min_gain", "max_gain"].iter().any(|key| value.get(key).is_some());
+        let config: Self = value.try_into()
+            .wrap_err_with(|| format!("failed to parse config file {}", path.display()))?;
+        Ok(Self { gain_range_set, ..config })
+    }
+
+    /// Checks that a profile of this name is configured
+    pub fn check_profile(&self, name: &str) -> Result<()> {
+        ensure!(
+            self.profiles.contains_key(name),
+            "unknown profile {name}, expected one of: {}",
+            self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+        Ok(())
+    }
+
+    /// Applies the options of the selected profile, which override the config file but not the
+    /// environment variables or the command line
+    fn apply_profile(&mut self) -> Result<()> {
+        let name = match &self.profile {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        self.check_profile(name)?;
+        let profile = self.profiles[name].clone();
+        if let Some(device) = profile.device {
+            self.device = Some(device);
+        }
+        if let Some(targets) = profile.targets {
+            // Like on the command line, the targets replace the ones from the config file
+            self.targets = targets;
+            self.left_targets.clear();
+            self.right_targets.clear();
+        }
+        if let Some(min_gain) = profile.min_gain {
+            self.min_gain = min_gain;
+            self.gain_range_set = true;
+        }
+        if let Some(max_gain) = profile.max_gain {
+            self.max_gain = max_gain;
+            self.gain_range_set = true;
+        }
+        if let Some(curve) = profile.curve {
+            self.curve = curve;
+        }
+        Ok(())
+    }
+
+    /// Applies `RUST_LOG` and the `VMSYNC_*` variables, which override the config file but not the
+    /// command line
+    fn apply_env(&mut self) -> Result<()> {
+        if let Ok(level) = env::var("RUST_LOG") {
+            // RUST_LOG is shared with other programs and may contain per-module filters we don't
+            // understand, logging isn't set up yet so print to stderr directly
+            match level.parse() {
+                Ok(level) => self.log_level = level,
+                Err(_) => eprintln!("Ignoring RUST_LOG={level}, expected error, warn, info, debug or trace"),
+            }
+        }
+        // Like on the command line, a target replaces the ones from the config file
+        if let Some(target) = env_value::<String>("VMSYNC_STRIP")? {
+            self.targets = vec![parse_value("VMSYNC_STRIP", Some(format!("strip:{target}")))?];
+        }
+        if let Some(target) = env_value("VMSYNC_TARGET")? {
+            self.targets = vec![target];
+        }
+        if let Some(min_gain) = env_value("VMSYNC_MIN_GAIN")? {
+            self.min_gain = min_gain;
+            self.gain_range_set = true;
+        }
+        if let Some(max_gain) = env_value("VMSYNC_MAX_GAIN")? {
+            self.max_gain = max_gain;
+            self.gain_range_set = true;
+        }
+        if let Some(curve) = env_value("VMSYNC_CURVE")? {
+            self.curve = curve;
+        }
+        if let Some(device) = env_value("VMSYNC_DEVICE")? {
+            self.device = Some(device);
+        }
+        // Service managers tend to make environment variables easier to set than arguments
+        if let Some(no_managed) = env_value("VMSYNC_NO_MANAGED")? {
+            self.no_managed = no_managed;
+        }
+        Ok(())
+    }
+
+    fn apply_args(&mut self, args: impl IntoIterator<Item = String>) -> Result<()> {
+        let mut args = args.into_iter();
+        // Targets from the command line replace the ones from the config file instead of adding to them
+        let mut targets_from_args = false;
+        let mut add_target = |config: &mut Self, kind: TargetKind, target| {
+            if !targets_from_args {
+                config.targets.clear();
+                config.left_targets.clear();
+                config.right_targets.clear();
+                targets_from_args = true;
+            }
+            match kind {
+                TargetKind::Master => config.targets.push(target),
+                TargetKind::Left => config.left_targets.push(target),
+                TargetKind::Right => config.right_targets.push(target),
+            }
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                // Both are applied by `load` already
+                "--config" | "--profile" => { args.next(); }
+                "--strip" => {
+                    let value = args.next().map(|value| format!("strip:{value}"));
+                    add_target(self, TargetKind::Master, parse_value(&arg, value)?)
+                }
+                "--strip-label" => {
+                    let value = args.next().map(|value| format!("label:{value}"));
+                    add_target(self, TargetKind::Master, parse_value(&arg, value)?)
+                }
+                "--target" => add_target(self, TargetKind::Master, parse_value(&arg, args.next())?),
+                "--left-target" => add_target(self, TargetKind::Left, parse_value(&arg, args.next())?),
+                "--right-target" => add_target(self, TargetKind::Right, parse_value(&arg, args.next())?),
+                "--min-gain" => {
+                    self.min_gain = parse_value(&arg, args.next())?;
+                    self.gain_range_set = true;
+                }
+                "--max-gain" => {
+                    self.max_gain = parse_value(&arg, args.next())?;
+                    self.gain_range_set = true;
+                }
+                "--gain-unit" => self.gain_unit = parse_value(&arg, args.next())?,
+                "--gain-cap" => self.gain_cap = parse_value(&arg, args.next())?,
+                "--use-system-db" => self.use_system_db = true,
+                "--invert" => self.invert = true,
+                "--curve" => self.curve = parse_value(&arg, args.next())?,
+                "--curve-file" => self.curve_file = Some(parse_value(&arg, args.next())?),
+                "--log-level" => self.log_level = parse_value(&arg, args.next())?,
+                "--log-format" => self.log_format = parse_value(&arg, args.next())?,
+                "--debounce-ms" => self.debounce_ms = parse_value(&arg, args.next())?,
+                "--min-change" => self.min_change = parse_value(&arg, args.next())?,
+                "--gain-step" => self.gain_step = parse_value(&arg, args.next())?,
+                "--device-settle-ms" => self.device_settle_ms = parse_value(&arg, args.next())?,
+                "--bidirectional" => self.bidirectional = true,
+                "--device" => self.device = Some(parse_value(&arg, args.next())?),
+                "--fallback-device" => self.fallback_device = Some(parse_value(&arg, args.next())?),
+                "--pin-device-id" => self.pin_device_id = Some(parse_value(&arg, args.next())?),
+                "--flow" => self.flow = parse_value(&arg, args.next())?,
+                "--role" => self.role = parse_value(&arg, args.next())?,
+                "--invert-mute" => self.invert_mute = true,
+                "--no-zero-mute" => self.zero_mute = false,
+                "--zero-mute-hysteresis" => self.zero_mute_hysteresis = parse_value(&arg, args.next())?,
+                "--floor-mute" => self.floor_mute = true,
+                "--mute-below" => self.mute_below = Some(parse_value(&arg, args.next())?),
+                "--mute-below-hysteresis" => self.mute_below_hysteresis = parse_value(&arg, args.next())?,
+                "--dry-run" => self.dry_run = true,
+                "--verify-writes" => self.verify_writes = true,
+                "--float-epsilon" => self.float_epsilon = parse_value(&arg, args.next())?,
+                "--gain-only" => self.gain_only = true,
+                "--mute-only" => self.mute_only = true,
+                "--safe-start" => self.safe_start = true,
+                "--verbose-events" => self.verbose_events = true,
+                "--sync-when" => self.sync_when = Some(parse_value(&arg, args.next())?),
+                "--gain-parameter" => self.gain_parameter = Some(parse_value(&arg, args.next())?),
+                "--mute-parameter" => self.mute_parameter = Some(parse_value(&arg, args.next())?),
+                "--map" => self.mappings.push(parse_value(&arg, args.next())?),
+                "--mute-macro" => self.mute_macro = Some(parse_value(&arg, args.next())?),
+                "--ramp-ms" => self.ramp_ms = parse_value(&arg, args.next())?,
+                "--max-slew" => self.max_slew = parse_value(&arg, args.next())?,
+                "--restart-delay-secs" => self.restart_delay_secs = parse_value(&arg, args.next())?,
+                "--restart-delay-max-secs" => self.restart_delay_max_secs = parse_value(&arg, args.next())?,
+                "--max-restarts" => self.max_restarts = parse_value(&arg, args.next())?,
+                "--no-managed" => self.no_managed = true,
+                "--fade-ms" => self.fade_ms = parse_value(&arg, args.next())?,
+                "--liveness-check-secs" => self.liveness_check_secs = parse_value(&arg, args.next())?,
+                "--watchdog-secs" => self.watchdog_secs = parse_value(&arg, args.next())?,
+                "--wait-for-voicemeeter" => self.wait_for_voicemeeter_secs = parse_value(&arg, args.next())?,
+                "--retries" => self.retries = parse_value(&arg, args.next())?,
+                "--no-tray" => self.tray = false,
+                "--notify" => self.notify = true,
+                "--deadzone" => self.deadzone = parse_value(&arg, args.next())?,
+                "--control-pipe" => self.control_pipe = Some(parse_value(&arg, args.next())?),
+                "--http-port" => self.http_port = Some(parse_value(&arg, args.next())?),
+                "--status-file" => self.status_file = Some(parse_value(&arg, args.next())?),
+                "--listen" => self.listen = Some(parse_value(&arg, args.next())?),
+                "--pause-hotkey" => self.pause_hotkey = Some(parse_value(&arg, args.next())?),
+                "--list-count" => self.list_count = Some(parse_value(&arg, args.next())?),
+                "--set-gain" => {
+                    ensure!(self.command.is_none(), "only one command can be run at a time");
+                    self.command = Some(Command::SetGain(parse_value(&arg, args.next())?));
+                }
+                flag => match Command::from_flag(flag) {
+                    Some(_) if self.command.is_some() => return Err(eyre!("only one command can be run at a time")),
+                    Some(command) => self.command = Some(command),
+                    None => return Err(eyre!("unknown argument: {arg}")),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for config in self.pair_configs() {
+            let index = config.pair.unwrap_or_default();
+            config.validate().wrap_err_with(|| format!("invalid pair {index}"))?;
+        }
+        ensure!(self.all_targets().next().is_some(), "at least one target is required");
+        for target in self.all_targets() {
+            ensure!(target.offset_db.is_finite(), "offset of {target} must be finite");
+        }
+        for target in self.left_targets.iter().chain(&self.right_targets) {
+            ensure!(target.label.is_none(), "{target} can't follow a channel, pick it by index instead");
+        }
+        // Only the master volume is synced back
+        ensure!(
+            !self.bidirectional || !self.targets.is_empty(),
+            "bidirectional sync requires a target that follows the master volume"
+        );
+        ensure!(
+            self.min_gain.is_finite() && self.max_gain.is_finite(),
+            "gain range must be finite"
+        );
+        ensure!(
+            self.min_gain < self.max_gain,
+            "min gain ({}) must be lower than max gain ({})",
+            self.min_gain,
+            self.max_gain
+        );
+        if self.gain_unit == GainUnit::Fader {
+            ensure!(
+                self.min_gain >= 0.0 && self.max_gain <= 100.0,
+                "fader positions must be between 0 and 100"
+            );
+        }
+        ensure!(self.fade_ms == 0 || self.ramp_ms == 0, "fading and ramping can't be combined");
+        ensure!(
+            self.max_slew.is_finite() && self.max_slew >= 0.0,
+            "max slew ({}) must be a positive number of dB per second",
+            self.max_slew
+        );
+        ensure!(self.fade_ms == 0 || self.max_slew == 0.0, "fading can't be combined with a max slew");
+        for (name, template) in [("gain", &self.gain_parameter), ("mute", &self.mute_parameter)] {
+            if let Some(template) = template {
+                ensure!(
+                    template.contains(PARAMETER_INDEX_PLACEHOLDER),
+                    "{name} parameter {template} must contain {PARAMETER_INDEX_PLACEHOLDER}"
+                );
+            }
+        }
+        // VoiceMeeter only fades the main gain
+        ensure!(self.fade_ms == 0 || self.gain_parameter.is_none(), "fading can't be combined with a gain parameter");
+        ensure!(
+            self.min_change.is_finite() && self.min_change >= 0.0,
+            "min change ({}) must be a positive number of dB",
+            self.min_change
+        );
+        ensure!(
+            self.gain_step.is_finite() && self.gain_step >= 0.0,
+            "gain step ({}) must be a positive number of dB",
+            self.gain_step
+        );
+        // Reading the gain in the middle of a fade would sync it back to Windows
+        ensure!(self.fade_ms == 0 || !self.bidirectional, "fading can't be combined with bidirectional sync");
+        ensure!(!(self.gain_only && self.mute_only), "gain only and mute only can't be combined");
+        ensure!(!(self.mute_only && self.bidirectional), "bidirectional sync only syncs the gain, it can't be combined with mute only");
+        ensure!(self.retries >= 1, "at least one retry is required");
+        ensure!(self.restart_delay_secs >= 1, "restart delay must be at least 1 second");
+        ensure!(
+            self.restart_delay_max_secs >= self.restart_delay_secs,
+            "max restart delay ({}s) must not be lower than the restart delay ({}s)",
+            self.restart_delay_max_secs,
+            self.restart_delay_secs
+        );
+        ensure!(self.gain_cap.is_finite(), "gain cap must be finite");
+        ensure!(
+            self.pin_device_id.is_none() || (self.device.is_none() && self.fallback_device.is_none()),
+            "a pinned device can't be combined with picking devices by name"
+        );
+        ensure!(!(self.invert && self.use_system_db), "the system volume in dB can't be inverted");
+        if let Some(gate) = &self.sync_when {
+            ensure!(gate.value.is_finite(), "value of {gate} must be finite");
+        }
+        if let Some(Command::SetGain(gain)) = self.command {
+            ensure!(
+                (MIN_GAIN..=MAX_GAIN).contains(&gain),
+                "gain to set ({gain}) must be between {MIN_GAIN} and {MAX_GAIN} dB"
+            );
+        }
+        ensure!(
+            (0.0..50.0).contains(&self.deadzone),
+            "deadzone ({}) must be at least 0 and below 50 percent",
+            self.deadzone
+        );
+        ensure!(
+            self.float_epsilon.is_finite() && self.float_epsilon > 0.0,
+            "float epsilon ({}) must be a positive number of dB",
+            self.float_epsilon
+        );
+        if let Some(threshold) = self.mute_below {
+            ensure!(threshold.is_finite(), "mute below threshold ({threshold}) must be finite");
+        }
+        ensure!(
+            self.mute_below_hysteresis.is_finite() && self.mute_below_hysteresis >= 0.0,
+            "mute below hysteresis ({}) must be a positive number of dB",
+            self.mute_below_hysteresis
+        );
+        ensure!(
+            (0.0..=100.0).contains(&self.zero_mute_hysteresis),
+            "zero mute hysteresis ({}) must be between 0 and 100 percent",
+            self.zero_mute_hysteresis
+        );
+        Ok(())
+    }
+}
+
+/// Fills in the `{index}` of `template`, or builds the usual parameter name without one
+fn parameter_name(template: Option<&str>, target: SyncTarget, name: &str) -> String {
+    match template {
+        Some(template) => template.replace(PARAMETER_INDEX_PLACEHOLDER, &target.index().to_string()),
+        None => target.parameter(name),
+    }
+}
+
+/// Parses an environment variable, `None` if it isn't set or empty
+fn env_value<T>(name: &str) -> Result<Option<T>>
+    where T: FromStr, T::Err: Into<Report> {
+    match env::var(name) {
+        Ok(value) if !value.is_empty() => parse_value(name, Some(value)).map(Some),
+        _ => Ok(None),
+    }
+}
+
+fn parse_value<T>(flag: &str, value: Option<String>) -> Result<T>
+    where T: FromStr, T::Err: Into<Report> {
+    let value = value.ok_or_else(|| eyre!("missing value for {flag}"))?;
+    value.parse::<T>()
+        .map_err(Into::into)
+        .wrap_err_with(|| format!("invalid value for {flag}: {value}"))
+}
@@ -0,0 +1,133 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
+use std::str::FromStr;
+use std::thread;
+use crossbeam::channel::{bounded, Sender};
+use eyre::{eyre, Report, Result, WrapErr};
+use log::{info, warn};
+use windows_sys::Win32::Foundation::{GetLastError, ERROR_PIPE_CONNECTED, HANDLE, INVALID_HANDLE_VALUE};
+use windows_sys::Win32::Storage::FileSystem::PIPE_ACCESS_DUPLEX;
+use windows_sys::Win32::System::Pipes::{ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_WAIT};
+use crate::ChannelEvent;
+use crate::win::to_wide;
+
+const PIPE_BUFFER_SIZE: u32 = 4096;
+
+/// Commands accepted on the control pipe, one per line
+pub enum ControlCommand {
+    /// Reply with the last synced volume and the gain computed from it
+    Status,
+    /// Read the Windows volume again and write it to all targets, even if nothing changed
+    Resync,
+    /// Sync to this strip instead of the configured targets
+    SetStrip(usize),
+    /// Switch to the profile of this name
+    Profile(String),
+}
+
+impl FromStr for ControlCommand {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("status"), None, _) => Ok(ControlCommand::Status),
+            (Some("resync"), None, _) => Ok(ControlCommand::Resync),
+            (Some("set-strip"), Some(index), None) => Ok(ControlCommand::SetStrip(
+                index.parse().wrap_err("invalid strip index")?
+            )),
+            (Some("profile"), Some(name), None) => Ok(ControlCommand::Profile(name.to_owned())),
+            _ => Err(eyre!("unknown command {s}, expected status, resync, set-strip <n> or profile <name>")),
+        }
+    }
+}
+
+/// A command received on the control pipe, the client waits until it is answered
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    reply: Sender<String>,
+}
+
+impl ControlRequest {
+    pub fn reply(self, text: String) {
+        // Nobody to tell if the client disconnected in the meantime
+        let _ = self.reply.send(text);
+    }
+}
+
+/// Serves `\\.\pipe\<name>` on a dedicated thread, one client at a time
+pub fn spawn(name: &str, send: Sender<ChannelEvent>) -> Result<()> {
+    let path = to_wide(&format!(r"\\.\pipe\{name}"));
+    // Create the first pipe right away so a bad name is reported at startup
+    let mut pipe = create_pipe(&path)?;
+    info!(r"Listening for control commands on \\.\pipe\{name}");
+    thread::Builder::new()
+        .name("control".to_owned())
+        .spawn(move || loop {
+            if let Err(err) = serve(pipe, &send) {
+                warn!("Control pipe client failed: {err:?}");
+            }
+            pipe = match create_pipe(&path) {
+                Ok(pipe) => pipe,
+                Err(err) => {
+                    warn!("Failed to recreate control pipe, no more commands will be accepted: {err:?}");
+                    return;
+                }
+            };
+        })
+        .wrap_err("failed to spawn control pipe thread")?;
+    Ok(())
+}
+
+fn create_pipe(path: &[u16]) -> Result<File> {
+    // SAFETY: path is a valid null terminated wide string that outlives the call
+    let handle = unsafe {
+        CreateNamedPipeW(
+            path.as_ptr(),
+            PIPE_ACCESS_DUPLEX,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            PIPE_BUFFER_SIZE,
+            PIPE_BUFFER_SIZE,
+            0,
+            std::ptr::null(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(eyre!("failed to create control pipe: error {}", unsafe { GetLastError() }));
+    }
+    // SAFETY: we own the handle, the file closes it when dropped
+    Ok(unsafe { File::from_raw_handle(handle as RawHandle) })
+}
+
+/// Waits for a client and answers its commands until it disconnects
+fn serve(pipe: File, send: &Sender<ChannelEvent>) -> Result<()> {
+    // SAFETY: the handle stays valid for as long as pipe is alive, no overlapped IO is used
+    let connected = unsafe { ConnectNamedPipe(pipe.as_raw_handle() as HANDLE, std::ptr::null_mut()) };
+    if connected == 0 {
+        // Clients that connect before we start waiting are reported as an error as well
+        let error = unsafe { GetLastError() };
+        if error != ERROR_PIPE_CONNECTED {
+            return Err(eyre!("failed to wait for control pipe client: error {error}"));
+        }
+    }
+    let mut writer = pipe.try_clone().wrap_err("failed to clone control pipe handle")?;
+    for line in BufReader::new(pipe).lines() {
+        let line = line.wrap_err("failed to read from control pipe")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match line.parse() {
+            Ok(command) => {
+                let (reply, response) = bounded(1);
+                send.send(ChannelEvent::Control(ControlRequest { command, reply }))
+                    .wrap_err("communication channel disconnected")?;
+                response.recv().wrap_err("control command was not answered")?
+            }
+            Err(err) => format!("error: {err}"),
+        };
+        writeln!(writer, "{reply}").wrap_err("failed to write to control pipe")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use std::thread;
+use std::time::Duration;
+use eyre::{eyre, Result, WrapErr};
+use log::warn;
+use win32_coreaudio::{DataFlow, Device, DeviceEnumerator, DeviceRole, DeviceState, StorageAccessMode};
+use win32_coreaudio::pkey::DEVICE_FRIENDLY_NAME;
+use crate::config::Config;
+
+/// How many times creating the device enumerator is retried, e.g. while Windows is still booting
+const ENUMERATOR_RETRIES: u32 = 5;
+/// Delay before the first retry, doubling with every retry after that
+const ENUMERATOR_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Creates the device enumerator, retrying for a few seconds since the audio subsystem may not be
+/// ready yet right after login
+pub fn device_enumerator() -> Result<DeviceEnumerator> {
+    let mut delay = ENUMERATOR_RETRY_DELAY;
+    for retry in 1..=ENUMERATOR_RETRIES {
+        match DeviceEnumerator::new() {
+            Ok(enumerator) => return Ok(enumerator),
+            Err(err) => {
+                warn!("Failed to setup device enumerator, retry {retry}/{ENUMERATOR_RETRIES} in {delay:?}: {err:?}");
+                thread::sleep(delay);
+                delay *= 2;
+            }
+        }
+    }
+    DeviceEnumerator::new().wrap_err("failed to setup device enumerator")
+}
+
+/// Picks the endpoint to sync from: the device with the ID in `config.pin_device_id` if set, else
+/// the device matching `config.device` if set and present, otherwise the default endpoint. If
+/// neither is available the device matching `config.fallback_device` is used before giving up on
+/// the default endpoint.
+pub fn select_device(config: &Config, enumerator: &mut DeviceEnumerator) -> Result<Device> {
+    let flow = config.flow.data_flow();
+    let role = config.role.device_role();
+    // A pinned device is never swapped for another one, not even while it is missing
+    if let Some(id) = &config.pin_device_id {
+        return find_device_by_id(enumerator, flow, id)?
+            .ok_or_else(|| eyre!("pinned device {id} is not present"));
+    }
+    if let Some(name) = &config.device {
+        if let Some(device) = find_named_device(enumerator, flow, name) {
+            return Ok(device);
+        }
+    } else {
+        match default_device(enumerator, flow, role) {
+            Ok(device) => return Ok(device),
+            // Nothing else to try without a fallback
+            Err(err) if config.fallback_device.is_none() => return Err(err),
+            Err(err) => warn!("No default device, trying the fallback device: {err:?}"),
+        }
+    }
+    if let Some(name) = &config.fallback_device {
+        if let Some(device) = find_named_device(enumerator, flow, name) {
+            return Ok(device);
+        }
+    }
+    default_device(enumerator, flow, role)
+}
+
+fn default_device(enumerator: &mut DeviceEnumerator, flow: DataFlow, role: DeviceRole) -> Result<Device> {
+    enumerator.get_default_audio_endpoint(flow, role)
+        .wrap_err("failed to get default audio endpoint")
+}
+
+/// Same as `find_device` but only logs why no device was found
+fn find_named_device(enumerator: &mut DeviceEnumerator, flow: DataFlow, name: &str) -> Option<Device> {
+    match find_device(enumerator, flow, name) {
+        Ok(Some(device)) => Some(device),
+        Ok(None) => {
+            warn!("No device matching \"{name}\" found");
+            None
+        }
+        Err(err) => {
+            warn!("Failed to look up device \"{name}\": {err:?}");
+            None
+        }
+    }
+}
+
+/// Finds the first active device whose friendly name contains `name`
+pub fn find_device(enumerator: &mut DeviceEnumerator, flow: DataFlow, name: &str) -> Result<Option<Device>> {
+    for device in active_devices(enumerator, flow)? {
+        match friendly_name(&device) {
+            Ok(device_name) if device_name.contains(name) => return Ok(Some(device)),
+            Ok(_) => {}
+            Err(err) => warn!("Skipping device while searching for \"{name}\": {err:?}"),
+        }
+    }
+    Ok(None)
+}
+
+/// Finds the active device with the endpoint ID `id`
+pub fn find_device_by_id(enumerator: &mut DeviceEnumerator, flow: DataFlow, id: &str) -> Result<Option<Device>> {
+    for device in active_devices(enumerator, flow)? {
+        match device_id(&device) {
+            Ok(device_id) if device_id == id => return Ok(Some(device)),
+            Ok(_) => {}
+            Err(err) => warn!("Skipping device while searching for {id}: {err:?}"),
+        }
+    }
+    Ok(None)
+}
+
+pub fn active_devices(enumerator: &mut DeviceEnumerator, flow: DataFlow) -> Result<Vec<Device>> {
+    let devices = enumerator.enum_audio_endpoints(flow, DeviceState::ACTIVE)
+        .wrap_err("failed to enumerate audio endpoints")?;
+    (0..devices.count().wrap_err("failed to count audio endpoints")?)
+        .map(|i| devices.get(i).wrap_err("failed to get audio endpoint"))
+        .collect()
+}
+
+pub fn device_id(device: &Device) -> Result<String> {
+    device.id()
+        .map(|id| id.to_string())
+        .wrap_err("failed to get device id")
+}
+
+pub fn friendly_name(device: &Device) -> Result<String> {
+    device.open_property_store(StorageAccessMode::Read)
+        .and_then(|store| store.get_string(&DEVICE_FRIENDLY_NAME))
+        .wrap_err("failed to get device name")
+}
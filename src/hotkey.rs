@@ -0,0 +1,47 @@
+use std::thread;
+use crossbeam::channel::{bounded, Sender};
+use eyre::{eyre, Result, WrapErr};
+use log::info;
+use windows_sys::Win32::Foundation::GetLastError;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::{RegisterHotKey, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MOD_SHIFT, MOD_WIN};
+use windows_sys::Win32::UI::WindowsAndMessaging::{GetMessageW, MSG, WM_HOTKEY};
+use crate::ChannelEvent;
+use crate::config::Hotkey;
+
+/// Only one hotkey is registered per thread
+const HOTKEY_ID: i32 = 1;
+
+/// Registers `hotkey` system wide and sends `ChannelEvent::TogglePause` whenever it is pressed.
+/// The hotkey belongs to a dedicated thread since it is delivered to the message queue of the
+/// thread that registered it.
+pub fn spawn(hotkey: Hotkey, send: Sender<ChannelEvent>) -> Result<()> {
+    let (registered_send, registered) = bounded(1);
+    thread::Builder::new()
+        .name("hotkey".to_owned())
+        .spawn(move || {
+            let modifiers = [(hotkey.ctrl, MOD_CONTROL), (hotkey.alt, MOD_ALT), (hotkey.shift, MOD_SHIFT), (hotkey.win, MOD_WIN)]
+                .into_iter()
+                .filter(|(pressed, _)| *pressed)
+                .fold(MOD_NOREPEAT, |modifiers, (_, modifier)| modifiers | modifier);
+            // SAFETY: no window is passed, the hotkey is posted to this thread's message queue
+            if unsafe { RegisterHotKey(0, HOTKEY_ID, modifiers, hotkey.key) } == 0 {
+                let error = unsafe { GetLastError() };
+                let _ = registered_send.send(Err(eyre!("failed to register hotkey {hotkey}, is it taken already? error {error}")));
+                return;
+            }
+            let _ = registered_send.send(Ok(()));
+            // SAFETY: MSG is plain data, all zeroes is a valid value
+            let mut msg: MSG = unsafe { std::mem::zeroed() };
+            // SAFETY: msg outlives the call, 0 and -1 mean quit and failure
+            while unsafe { GetMessageW(&mut msg, 0, 0, 0) } > 0 {
+                // Nobody left to pause if the channel is gone
+                if msg.message == WM_HOTKEY && send.send(ChannelEvent::TogglePause).is_err() {
+                    return;
+                }
+            }
+        })
+        .wrap_err("failed to spawn hotkey thread")?;
+    registered.recv().wrap_err("hotkey thread stopped before registering the hotkey")??;
+    info!("Press {hotkey} to pause or resume syncing");
+    Ok(())
+}
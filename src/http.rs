@@ -0,0 +1,67 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use eyre::{eyre, Result, WrapErr};
+use log::{info, warn};
+use serde::Serialize;
+use tiny_http::{Header, Response, Server};
+
+/// Sync state reported by the status server
+#[derive(Clone, Default, PartialEq, Serialize)]
+pub struct Status {
+    /// Whether VoiceMeeter was running at the last check
+    pub connected: bool,
+    /// Last synced Windows volume scalar (0.0 - 1.0)
+    pub volume: Option<f32>,
+    /// Whether Windows was muted at the last sync
+    pub mute: Option<bool>,
+    /// Gain (before per-target offsets) last written to the targets, in dB
+    pub gain: Option<f32>,
+    /// Whether the targets were last muted
+    pub muted: Option<bool>,
+    /// Strips and buses that are synced, e.g. `Strip[3]`
+    pub targets: Vec<String>,
+}
+
+/// Serves the current `Status` as JSON on localhost from a dedicated thread
+pub struct StatusServer {
+    status: Arc<Mutex<Status>>,
+}
+
+impl StatusServer {
+    pub fn spawn(port: u16) -> Result<Self> {
+        let server = Server::http(("127.0.0.1", port))
+            .map_err(|err| eyre!("failed to start HTTP server on port {port}: {err}"))?;
+        info!("Serving status on http://127.0.0.1:{port}/status");
+        let status = Arc::new(Mutex::new(Status::default()));
+        let server_status = status.clone();
+        thread::Builder::new()
+            .name("http".to_owned())
+            .spawn(move || {
+                for request in server.incoming_requests() {
+                    let response = match request.url() {
+                        "/" | "/status" => {
+                            let status = server_status.lock().unwrap().clone();
+                            match serde_json::to_string(&status) {
+                                Ok(json) => Response::from_string(json).with_header(
+                                    Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+                                        .expect("static header is valid"),
+                                ),
+                                Err(err) => Response::from_string(format!("failed to serialize status: {err}"))
+                                    .with_status_code(500),
+                            }
+                        }
+                        _ => Response::from_string("not found").with_status_code(404),
+                    };
+                    if let Err(err) = request.respond(response) {
+                        warn!("Failed to answer HTTP request: {err:?}");
+                    }
+                }
+            })
+            .wrap_err("failed to spawn HTTP server thread")?;
+        Ok(Self { status })
+    }
+
+    pub fn set_status(&self, status: Status) {
+        *self.status.lock().unwrap() = status;
+    }
+}
@@ -0,0 +1,31 @@
+use eyre::{eyre, Result};
+use windows_sys::Win32::Foundation::{CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, HANDLE};
+use windows_sys::Win32::System::Threading::CreateMutexW;
+use crate::win::to_wide;
+
+/// Named mutex held by the running instance, released when dropped or when the process dies
+pub struct InstanceGuard(HANDLE);
+
+impl InstanceGuard {
+    /// Returns `None` if another instance already holds the guard
+    pub fn acquire(name: &str) -> Result<Option<Self>> {
+        let name = to_wide(&format!("Local\\{name}"));
+        // SAFETY: name is a valid null terminated wide string that outlives the call
+        let handle = unsafe { CreateMutexW(std::ptr::null(), 0, name.as_ptr()) };
+        if handle == 0 {
+            return Err(eyre!("failed to create instance mutex: error {}", unsafe { GetLastError() }));
+        }
+        // The mutex exists already if another instance created it first, we still get a handle to it
+        if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+            unsafe { CloseHandle(handle) };
+            return Ok(None);
+        }
+        Ok(Some(Self(handle)))
+    }
+}
+
+impl Drop for InstanceGuard {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
@@ -0,0 +1,72 @@
+use std::io::Read;
+use std::thread;
+use crossbeam::channel::Sender;
+use eyre::{eyre, Result, WrapErr};
+use log::{info, warn};
+use serde::Deserialize;
+use tiny_http::{Method, Request, Response, Server};
+use crate::{ChannelEvent, CurrentVolume};
+
+/// Largest request body accepted, volume messages are tiny
+const MAX_BODY_SIZE: u64 = 1024;
+
+/// Volume sent by another machine, e.g. `{"volume":0.5,"mute":false}`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VolumeMessage {
+    /// Volume scalar (0.0 - 1.0)
+    volume: f32,
+    #[serde(default)]
+    mute: bool,
+}
+
+/// Accepts volumes POSTed as JSON to `http://<addr>/volume` on a dedicated thread and syncs them
+/// like volume changes of the local device
+pub fn spawn(addr: &str, send: Sender<ChannelEvent>) -> Result<()> {
+    let server = Server::http(addr)
+        .map_err(|err| eyre!("failed to listen for volumes on {addr}: {err}"))?;
+    info!("Listening for volumes on http://{addr}/volume");
+    thread::Builder::new()
+        .name("listen".to_owned())
+        .spawn(move || {
+            for mut request in server.incoming_requests() {
+                let response = match (request.method(), request.url()) {
+                    (Method::Post, "/volume") => match read_volume(&mut request) {
+                        Ok(volume) => {
+                            // Nobody left to apply the volume if the channel is gone. Only the
+                            // first pair is synced from the network.
+                            if send.send(ChannelEvent::VolumeChange(0, volume)).is_err() {
+                                return;
+                            }
+                            Response::from_string("").with_status_code(204)
+                        }
+                        Err(err) => Response::from_string(format!("{err:#}")).with_status_code(400),
+                    },
+                    (_, "/volume") => Response::from_string("expected POST").with_status_code(405),
+                    _ => Response::from_string("not found").with_status_code(404),
+                };
+                if let Err(err) = request.respond(response) {
+                    warn!("Failed to answer volume request: {err:?}");
+                }
+            }
+        })
+        .wrap_err("failed to spawn volume listener thread")?;
+    Ok(())
+}
+
+fn read_volume(request: &mut Request) -> Result<CurrentVolume> {
+    let mut body = String::new();
+    request.as_reader()
+        .take(MAX_BODY_SIZE)
+        .read_to_string(&mut body)
+        .wrap_err("failed to read request body")?;
+    let message: VolumeMessage = serde_json::from_str(&body).wrap_err("invalid volume message")?;
+    Ok(CurrentVolume {
+        new_volume: message.volume,
+        mute: message.mute,
+        level_db: None,
+        channel_volumes: None,
+        event_id: None,
+        remote: true,
+    })
+}
@@ -1,46 +1,203 @@
+mod autostart;
+mod config;
+mod control;
+mod device;
+mod hotkey;
+mod http;
+mod instance;
+mod listen;
+mod metrics;
+mod notify;
+mod persist;
+mod power;
+mod service;
+mod status_file;
+mod tray;
 mod vm;
+mod watch;
+mod win;
 
-use std::{env, thread};
+use std::{env, fmt, thread};
+use std::collections::HashSet;
 use std::env::args;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::process::{Command, Stdio};
-use std::time::Duration;
-use eyre::{Result, WrapErr};
+use std::time::{Duration, Instant};
+use eyre::{ensure, eyre, Report, Result, WrapErr};
 use fern::colors::ColoredLevelConfig;
-use log::{info, warn};
-use win32_coreaudio::{AudioEndpointVolumeCallback, AudioEndpointVolumeCallbackHandle, DataFlow, DeviceEnumerator, DeviceRole, NotificationClient, NotificationData};
-use crate::vm::VoiceMeeterController;
-use crossbeam::channel::{Sender, unbounded};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use win32_coreaudio::{AudioEndpointVolume, AudioEndpointVolumeCallback, AudioEndpointVolumeCallbackHandle, DataFlow, Device, DeviceEnumerator, DeviceRole, DeviceState, NotificationClient, NotificationData};
+use crate::config::{Command as Subcommand, Config, Curve, GainUnit, LogFormat, SyncTarget, Target};
+use crate::control::{ControlCommand, ControlRequest};
+use crate::device::{active_devices, device_enumerator, device_id, friendly_name, select_device};
+use crate::http::{Status, StatusServer};
+use crate::instance::InstanceGuard;
+use crate::metrics::{Counts, Metrics};
+use crate::notify::notify;
+use crate::status_file::StatusFile;
+use crate::tray::Tray;
+use crate::vm::{channel_counts, default_gain_range, nearly_equal, MAX_GAIN, MIN_GAIN, ParameterSink, VoiceMeeterController};
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender, unbounded};
 use win32_coreaudio::string::WinStr;
 
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
 const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Exit code of the managed program when another instance is already running
+const ALREADY_RUNNING_EXIT_CODE: i32 = 2;
+/// Exit code of the managed program when the config does not match the running VoiceMeeter
+const INVALID_CONFIG_EXIT_CODE: i32 = 3;
+/// Exit code of any other failure, which restarting may fix
+const FAILURE_EXIT_CODE: i32 = 1;
+/// The restart delay of the supervisor starts over once the managed program ran this long
+const STABLE_RUN_DURATION: Duration = Duration::from_secs(60);
+/// How often VoiceMeeter is polled for changes in bidirectional mode
+const REVERSE_SYNC_INTERVAL: Duration = Duration::from_millis(100);
+/// Time VoiceMeeter gets to apply a write before `--self-test` reads it back
+const SELF_TEST_SETTLE_TIME: Duration = Duration::from_millis(100);
+/// How often to check whether VoiceMeeter started while waiting for it at startup
+const STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How often the metrics are logged, if they changed
+const METRICS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+/// Time between gain writes while ramping
+const RAMP_STEP_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Context of the errors restarting won't fix since the config doesn't match the running
+/// VoiceMeeter, reported with `INVALID_CONFIG_EXIT_CODE`
+#[derive(Debug)]
+struct InvalidConfig;
+
+impl fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the config does not match the running VoiceMeeter setup")
+    }
+}
+
+/// Error for another instance holding the instance guard, reported with `ALREADY_RUNNING_EXIT_CODE`
+#[derive(Debug)]
+struct AlreadyRunning;
+
+impl fmt::Display for AlreadyRunning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "another instance of {APP_NAME} is already running")
+    }
+}
+
+/// Exit code `err` should be reported with, tells whoever restarts the program whether that helps
+fn exit_code(err: &Report) -> i32 {
+    if err.downcast_ref::<InvalidConfig>().is_some() {
+        INVALID_CONFIG_EXIT_CODE
+    } else if err.downcast_ref::<AlreadyRunning>().is_some() {
+        ALREADY_RUNNING_EXIT_CODE
+    } else {
+        FAILURE_EXIT_CODE
+    }
+}
+
+/// Exits with the exit code of the error if restarting won't fix it, so the supervisor stops
+/// relaunching. Other errors are returned from `main` as usual.
+fn exit_on_permanent_failure(result: Result<()>) -> Result<()> {
+    if let Err(err) = &result {
+        let code = exit_code(err);
+        if code != FAILURE_EXIT_CODE {
+            error!("{err:?}");
+            std::process::exit(code);
+        }
+    }
+    result
+}
 
 enum ChannelEvent {
-    VolumeChange(CurrentVolume),
-    DeviceChange
+    /// The volume of the device of the pair at this index changed, always `0` without pairs
+    VolumeChange(usize, CurrentVolume),
+    /// The devices changed in a way that matters to the pair at this index
+    DeviceChange(usize),
+    /// VoiceMeeter is available again after the connection was lost
+    Reconnected,
+    /// The config file changed, or the profile of this name was selected
+    ConfigReload(Option<String>),
+    Control(ControlRequest),
+    /// The pause hotkey was pressed
+    TogglePause,
+    /// The Windows callbacks may have gone stale and should be registered again
+    RenewCallbacks,
+    Shutdown,
 }
 
+#[derive(Clone, Copy, Deserialize, Serialize)]
 struct CurrentVolume {
     new_volume: f32,
     mute: bool,
+    /// Master volume in dB as reported by Windows, only known if it was queried
+    #[serde(default)]
+    level_db: Option<f32>,
+    /// Volume scalars of the left and right channel, only known if they were queried
+    #[serde(default)]
+    channel_volumes: Option<(f32, f32)>,
+    /// Sequence number of the notification this came from in `--verbose-events` mode
+    #[serde(skip)]
+    event_id: Option<u64>,
+    /// Sent to `--listen` instead of coming from a local device
+    #[serde(skip)]
+    remote: bool,
+}
+
+/// Hands out the sequence numbers logged in `--verbose-events` mode, shared by all callbacks
+#[derive(Clone, Default)]
+struct EventCounter(Arc<AtomicU64>);
+
+impl EventCounter {
+    /// Logs an event with the next sequence number and the time it arrived, returns the number
+    fn log(&self, event: &str) -> u64 {
+        let id = self.0.fetch_add(1, Ordering::Relaxed) + 1;
+        info!("Event #{id} at {}: {event}", chrono::Local::now().format("%H:%M:%S%.3f"));
+        id
+    }
 }
 
 fn main() -> Result<()> {
+    let mut args: Vec<String> = args().skip(1).collect();
+    let managed = args.first().map(String::as_str) == Some("managed");
+    if managed {
+        args.remove(0);
+    }
+    // Load in both modes so the supervisor fails fast on a bad config instead of relaunching a
+    // child that can never start. Both processes get the same arguments and thus read the same
+    // config file.
+    let config = Config::load(&args).wrap_err("failed to load config")?;
+
     // Setup logging
-    let log_colors = ColoredLevelConfig::new();
-    fern::Dispatch::new()
-        // Perform allocation-free log formatting
-        .format(move |out, message, record| {
-            out.finish(format_args!(
-                "{}[{}][{}] {}",
-                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
-                record.target(),
-                log_colors.color(record.level()),
-                message
-            ))
-        })
+    let dispatch = match config.log_format {
+        LogFormat::Text => {
+            let log_colors = ColoredLevelConfig::new();
+            fern::Dispatch::new()
+                // Perform allocation-free log formatting
+                .format(move |out, message, record| {
+                    out.finish(format_args!(
+                        "{}[{}][{}] {}",
+                        chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                        record.target(),
+                        log_colors.color(record.level()),
+                        message
+                    ))
+                })
+        }
+        // One object per line for log collectors
+        LogFormat::Json => fern::Dispatch::new().format(|out, message, record| {
+            let line = serde_json::json!({
+                "timestamp": chrono::Local::now().to_rfc3339(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": message.to_string(),
+            });
+            out.finish(format_args!("{line}"))
+        }),
+    };
+    dispatch
         // Add blanket level filter -
-        .level(log::LevelFilter::Trace)
+        .level(config.log_level)
         // Output to stdout, files, and other Dispatch configurations
         .chain(std::io::stdout())
         // Apply globally
@@ -48,108 +205,1928 @@ fn main() -> Result<()> {
         .expect("Failed to initialize logging!");
 
     info!("Starting {APP_NAME} v{APP_VERSION}");
+    if config.path.exists() {
+        info!("Loaded config from {}", config.path.display());
+    } else {
+        info!("No config file found at {}, using defaults", config.path.display());
+    }
+
+    // Arguments without the subcommand, i.e. the ones a normal run would use
+    let sync_args: Vec<String> = args.iter()
+        .filter(|arg| !Subcommand::is_flag(arg))
+        .cloned()
+        .collect();
+    match config.command {
+        Some(Subcommand::InstallAutostart) => {
+            let command = autostart::install(&sync_args)?;
+            info!("Registered to start at login: {command}");
+            return Ok(());
+        }
+        Some(Subcommand::UninstallAutostart) => {
+            if autostart::uninstall()? {
+                info!("Removed start at login entry.");
+            } else {
+                info!("Not registered to start at login, nothing to remove.");
+            }
+            return Ok(());
+        }
+        Some(Subcommand::ListDevices) => return list_devices(&config),
+        Some(Subcommand::ListParameters) => return list_parameters(&config),
+        // Nothing to supervise, the program is done right after the first write
+        Some(Subcommand::Once) => return exit_on_permanent_failure(start(config)),
+        Some(Subcommand::InstallService) => {
+            service::install(&sync_args)?;
+            info!("Installed the {APP_NAME} service, it starts with Windows from now on.");
+            return Ok(());
+        }
+        Some(Subcommand::UninstallService) => {
+            service::uninstall()?;
+            info!("Removed the {APP_NAME} service.");
+            return Ok(());
+        }
+        // The service manager restarts the service if it crashes, so no supervisor is needed
+        Some(Subcommand::RunAsService) => return service::run(),
+        Some(Subcommand::SelfTest) => return self_test(config),
+        Some(Subcommand::SetGain(gain)) => return set_gain(config, gain),
+        Some(Subcommand::Monitor) => return monitor(config),
+        Some(Subcommand::PrintConfig) => {
+            println!("{config:#?}");
+            return Ok(());
+        }
+        None => {}
+    }
 
     // This is necessary because the VoiceMeeter SDK will crash the program if VoiceMeeter is not
     // running...
     // Thanks VoiceMeeter...
-    if let Some("managed") = args().nth(1).as_deref() {
-        info!("Launched in managed mode.");
-        start()
+    if managed || config.no_managed {
+        if managed {
+            info!("Launched in managed mode.");
+        } else {
+            // Errors are returned from main as usual, nobody relaunches the program
+            info!("Launched without a supervisor, crashes won't be restarted.");
+        }
+        // Only the managed program takes the guard, the supervisor of another instance stops
+        // relaunching once it sees the exit code
+        let _instance_guard = match InstanceGuard::acquire(APP_NAME)? {
+            Some(guard) => guard,
+            None => {
+                error!("Another instance of {APP_NAME} is already running, exiting.");
+                std::process::exit(ALREADY_RUNNING_EXIT_CODE);
+            }
+        };
+        exit_on_permanent_failure(start(config))
     } else {
         info!("Launched in non-managed mode, booting managed program...");
+        // Ctrl+C is delivered to the managed program as well, so all we have to do is wait for it
+        // to exit and not boot it again
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let handler_shutting_down = shutting_down.clone();
+        ctrlc::set_handler(move || handler_shutting_down.store(true, Ordering::SeqCst))
+            .wrap_err("failed to install shutdown handler")?;
+        let mut restart_delay = Duration::from_secs(config.restart_delay_secs);
+        let max_restart_delay = Duration::from_secs(config.restart_delay_max_secs);
+        let mut restarts = 0;
         loop {
-            let _ = Command::new(env::current_exe()?)
+            let launched = Instant::now();
+            let status = Command::new(env::current_exe()?)
                 .arg("managed")
+                .args(&args)
                 .stdout(Stdio::inherit())
                 .stderr(Stdio::inherit())
                 .stdin(Stdio::inherit())
                 .spawn()?
                 .wait();
-            info!("Managed program crashed, booting it again in 5s...");
-            thread::sleep(Duration::from_secs(5));
+            // Passed on as they are, so scripts can tell them apart in supervisor mode as well
+            match status.map(|status| status.code()) {
+                Ok(Some(ALREADY_RUNNING_EXIT_CODE)) => {
+                    error!("Another instance of {APP_NAME} is already running, exiting.");
+                    std::process::exit(ALREADY_RUNNING_EXIT_CODE);
+                }
+                Ok(Some(INVALID_CONFIG_EXIT_CODE)) => {
+                    error!("The config does not match the running VoiceMeeter setup, exiting.");
+                    std::process::exit(INVALID_CONFIG_EXIT_CODE);
+                }
+                _ => {}
+            }
+            if shutting_down.load(Ordering::SeqCst) {
+                info!("Managed program exited, shutting down.");
+                return Ok(());
+            }
+            // Only crashes in a row count, a program that ran fine for a while starts over
+            if launched.elapsed() >= STABLE_RUN_DURATION {
+                restart_delay = Duration::from_secs(config.restart_delay_secs);
+                restarts = 0;
+            }
+            restarts += 1;
+            if config.max_restarts > 0 && restarts > config.max_restarts {
+                return Err(eyre!("managed program crashed {restarts} times in a row, giving up"));
+            }
+            let delay_secs = restart_delay.as_secs();
+            info!("Managed program crashed, booting it again in {delay_secs}s...");
+            if config.notify {
+                notify(&format!("Volume sync crashed, restarting in {delay_secs}s..."));
+            }
+            thread::sleep(restart_delay);
+            restart_delay = (restart_delay * 2).min(max_restart_delay);
+        }
+    }
+}
+
+/// Prints the name and ID of every active device, the names can be passed to `--device` as is
+fn list_devices(config: &Config) -> Result<()> {
+    let mut enumerator = device_enumerator()?;
+    for device in active_devices(&mut enumerator, config.flow.data_flow())? {
+        let name = friendly_name(&device).unwrap_or_else(|err| format!("<unknown: {err}>"));
+        let id = device_id(&device).unwrap_or_else(|err| format!("<unknown: {err}>"));
+        println!("{name}\n    id: {id}");
+    }
+    Ok(())
+}
+
+/// Prints the current gain, mute and label of the strips and buses
+fn list_parameters(config: &Config) -> Result<()> {
+    let mut controller = VoiceMeeterController::new(false, config.retries);
+    let (strips, buses) = match config.list_count {
+        Some(count) => (count, count),
+        None => channel_counts(controller.get_voicemeeter_type()?)
+            .ok_or_else(|| eyre!("unknown VoiceMeeter type, use --list-count to choose how many to list"))?,
+    };
+    let channels = (0..strips).map(SyncTarget::Strip)
+        .chain((0..buses).map(SyncTarget::Bus));
+    for channel in channels {
+        let label = controller.get_parameter_string(&channel.parameter("Label"))
+            .unwrap_or_else(|err| format!("<unknown: {err}>"));
+        let gain = controller.get_parameter_float(&channel.parameter("Gain"))
+            .map_or_else(|err| format!("<unknown: {err}>"), |gain| format!("{gain:.1} dB"));
+        let mute = controller.get_parameter_float(&channel.parameter("Mute"))
+            .map_or_else(|err| format!("<unknown: {err}>"), |mute| if mute != 0.0 { "on" } else { "off" }.to_owned());
+        let channel = channel.to_string();
+        println!("{channel:<9} label: \"{label}\", gain: {gain}, mute: {mute}");
+    }
+    Ok(())
+}
+
+/// Writes a gain to every target, reads it back and restores the original state
+fn self_test(mut config: Config) -> Result<()> {
+    let mut controller = VoiceMeeterController::new(false, config.retries);
+    prepare_targets(&mut config, &mut controller)?;
+    let mut failed = 0;
+    for target in config.all_targets() {
+        match self_test_target(&config, target.channel, &mut controller) {
+            Ok(()) => println!("{target}: ok"),
+            Err(err) => {
+                println!("{target}: failed: {err:?}");
+                failed += 1;
+            }
         }
     }
+    ensure!(failed == 0, "{failed} target(s) failed the self test");
+    Ok(())
+}
+
+fn self_test_target(config: &Config, target: SyncTarget, controller: &mut VoiceMeeterController) -> Result<()> {
+    let original = TargetState::read(config, target, controller).wrap_err("failed to read original state")?;
+    // Make sure the test gain is not what the target already had
+    let test_gain = if (original.gain + 12.0).abs() < 1.0 { -24.0 } else { -12.0 };
+    let param = config.gain_param(target);
+    let result = controller.set_parameter_float(&param, test_gain)
+        .and_then(|_| controller.update_parameters_dirty())
+        .and_then(|_| {
+            thread::sleep(SELF_TEST_SETTLE_TIME);
+            // Refreshes the values returned by the getters
+            controller.update_parameters_dirty()?;
+            controller.get_parameter_float(&param)
+        })
+        .and_then(|gain| {
+            ensure!(
+                nearly_equal(gain, test_gain, config.float_epsilon),
+                "wrote a gain of {test_gain} dB but read back {gain} dB"
+            );
+            Ok(())
+        });
+    // Restore even if the test failed halfway
+    original.write(controller).wrap_err("failed to restore original state")?;
+    result
 }
 
-fn start() -> Result<()> {
-    let mut enumerator = DeviceEnumerator::new()
-        .wrap_err("failed to setup device enumerator")?;
+/// Writes `gain` to all targets once, as is without offsets or the gain cap
+fn set_gain(mut config: Config, gain: f32) -> Result<()> {
+    let mut controller = VoiceMeeterController::new(config.dry_run, config.retries);
+    prepare_targets(&mut config, &mut controller)?;
+    for target in config.all_targets() {
+        controller.set_parameter_float(&config.gain_param(target.channel), gain)?;
+    }
+    controller.update_parameters_dirty()?;
+    info!("Set the gain of {} target(s) to {gain} dB.", config.all_targets().count());
+    Ok(())
+}
 
-    let mut controller = VoiceMeeterController::new();
+/// Resolves the labels of the targets and checks that they exist, for commands that only touch
+/// them once
+fn prepare_targets(config: &mut Config, controller: &mut VoiceMeeterController) -> Result<()> {
+    if config.targets.iter().any(|target| target.label.is_some()) {
+        let labels = strip_labels(controller)?;
+        resolve_labels(&mut config.targets, &labels)?;
+    }
+    validate_targets(config, controller)
+}
 
+fn start(config: Config) -> Result<()> {
     let (send, recv) = unbounded::<ChannelEvent>();
-    // Do not drop the device change handle, otherwise the event listener will be unregistered
-    let _device_change_handle = enumerator.register_endpoint_notification(DeviceChangeCallback {
-        send: send.clone()
-    });
-    // Do not drop the volume change handle, otherwise the event listener will be unregistered
-    let mut _vol_change_handle = setup_volume_cb(&mut controller, &mut enumerator, send.clone());
+    send_shutdown_on_ctrlc(send.clone())?;
+    run(config, send, recv)
+}
+
+fn send_shutdown_on_ctrlc(send: Sender<ChannelEvent>) -> Result<()> {
+    ctrlc::set_handler(move || {
+        if let Err(e) = send.send(ChannelEvent::Shutdown) {
+            warn!("Failed to send shutdown event: {e:?}");
+        }
+    }).wrap_err("failed to install shutdown handler")
+}
+
+/// Prints the volume of the device that would be synced from whenever it changes, until Ctrl+C.
+/// VoiceMeeter is never touched, which tells problems on the Windows side apart from the rest.
+fn monitor(config: Config) -> Result<()> {
+    let (send, recv) = unbounded::<ChannelEvent>();
+    send_shutdown_on_ctrlc(send.clone())?;
+    let metrics = Arc::new(Metrics::default());
+    let mut devices = Devices {
+        enumerator: device_enumerator()?,
+        pair: 0,
+        send: send.clone(),
+        events: None,
+        // Every notification is worth printing
+        follow_channels: true,
+        metrics: metrics.clone(),
+        source: None,
+    };
+    let _device_change_handle = devices.enumerator.register_endpoint_notification(
+        DeviceChangeCallback::new(&config, send, None, metrics),
+    );
+    match devices.attach(&config) {
+        Ok(()) => print_volume(&devices, devices.volume()?)?,
+        Err(err) => warn!("No device to monitor, waiting for one to appear: {err:?}"),
+    }
     loop {
-        let evt = recv.recv().wrap_err("communication channel disconnected")?;
+        match recv.recv().wrap_err("communication channel disconnected")? {
+            ChannelEvent::VolumeChange(_, volume) => print_volume(&devices, volume)?,
+            ChannelEvent::DeviceChange(_) => {
+                let unchanged = match (devices.wanted_id(&config), devices.attached_id()) {
+                    (Ok(wanted), Some(attached)) => wanted == attached,
+                    _ => false,
+                };
+                if unchanged {
+                    continue;
+                }
+                match devices.attach(&config) {
+                    Ok(()) => print_volume(&devices, devices.volume()?)?,
+                    Err(err) => warn!("No device to monitor, waiting for one to appear: {err:?}"),
+                }
+            }
+            ChannelEvent::Shutdown => return Ok(()),
+            // Nothing else is set up to send events
+            _ => {}
+        }
+    }
+}
+
+/// Prints `volume` as JSON, along with the dB level and channel volumes the notification lacks
+fn print_volume(devices: &Devices, mut volume: CurrentVolume) -> Result<()> {
+    volume.level_db = volume.level_db.or_else(|| devices.level_db().ok());
+    volume.channel_volumes = volume.channel_volumes.or_else(|| devices.channel_volumes().ok());
+    let json = serde_json::to_string(&volume).wrap_err("failed to serialize volume")?;
+    println!("{} {json}", chrono::Local::now().format("%H:%M:%S%.3f"));
+    Ok(())
+}
+
+/// Syncs until a `ChannelEvent::Shutdown` arrives on `recv`, `send` has to belong to the same
+/// channel
+fn run(config: Config, send: Sender<ChannelEvent>, recv: Receiver<ChannelEvent>) -> Result<()> {
+    // Without pairs the config itself is the only one
+    let configs = if config.pairs.is_empty() {
+        vec![config]
+    } else {
+        config.pair_configs()
+    };
+    run_sync(configs, send, recv)
+}
+
+/// Everything synced for one pair of the config, or for the whole config if it has no pairs
+struct PairSync {
+    config: Config,
+    state: SyncState,
+    devices: Devices,
+    ramp: Option<Ramp>,
+    /// When to apply the volume of a device that was just switched to, once it settled
+    settled_sync: Option<Instant>,
+    /// What the targets looked like before we took them over so they can be restored on exit
+    original_states: Vec<TargetState>,
+}
+
+impl PairSync {
+    /// Checks the targets against VoiceMeeter and applies the volume the pair starts from
+    fn start(mut config: Config, controller: &mut VoiceMeeterController, devices: Devices) -> Result<Self> {
+        // Restarting won't fix a target that doesn't exist, let the supervisor know. Labels can't
+        // be resolved without VoiceMeeter though, so that failure is left to the supervisor to
+        // retry.
+        if config.targets.iter().any(|target| target.label.is_some()) {
+            match strip_labels(controller) {
+                Ok(labels) => resolve_labels(&mut config.targets, &labels).wrap_err(InvalidConfig)?,
+                // Some VoiceMeeter versions are unreliable with string parameters
+                Err(err) => {
+                    warn!("Failed to read strip labels, using the fallback indices: {err:?}");
+                    use_label_fallbacks(&mut config.targets)?;
+                }
+            }
+        }
+        validate_targets(&config, controller).wrap_err(InvalidConfig)?;
+        use_edition_gain_range(&mut config, controller);
+        let original_states = config.all_targets()
+            .filter_map(|target| match TargetState::read(&config, target.channel, controller) {
+                Ok(state) => Some(state),
+                Err(err) => {
+                    warn!("Failed to read original state of {target}, it will not be restored on exit: {err:?}");
+                    None
+                }
+            })
+            .collect();
+        let state = SyncState {
+            volume_file: Some(persist::volume_file(config.pair)?),
+            ..SyncState::default()
+        };
+        let mut pair = Self {
+            config,
+            state,
+            devices,
+            ramp: None,
+            settled_sync: None,
+            original_states,
+        };
+        let once = pair.config.command == Some(Subcommand::Once);
+        // Start from where the last run left off instead of whatever VoiceMeeter loaded, unless
+        // asked to apply the current volume
+        let saved_volume = if once {
+            None
+        } else {
+            pair.state.volume_file.as_deref().and_then(persist::load_volume)
+        };
+        let Self { config, state, devices, .. } = &mut pair;
+        match devices.attach(config) {
+            Ok(()) if config.safe_start => safe_start(config, controller, state, devices, saved_volume),
+            Ok(()) => sync_device_volume(config, controller, state, devices, saved_volume),
+            Err(err) if once => return Err(err),
+            Err(err) => warn!("No device to sync from, waiting for one to appear: {err:?}"),
+        }
+        Ok(pair)
+    }
+
+    /// Catches up with whatever is due at `now`: the volume of a device that settled, the next
+    /// step of a ramp or, if `dirty`, gain changes made in VoiceMeeter
+    fn tick(&mut self, controller: &mut VoiceMeeterController, now: Instant, dirty: bool) {
+        if matches!(self.settled_sync, Some(sync_at) if now >= sync_at) {
+            self.settled_sync = None;
+            sync_device_volume(&self.config, controller, &mut self.state, &self.devices, None);
+        }
+        if let Some(active_ramp) = &self.ramp {
+            let (output, done) = active_ramp.output_at(now);
+            if done {
+                self.ramp = None;
+            }
+            if let Err(err) = write_output(&self.config, controller, &mut self.state, output) {
+                warn!("Failed to update current volume: {err:?}");
+            }
+        } else if dirty {
+            if let Some(source) = &self.devices.source {
+                if let Err(err) = reverse_sync(&self.config, controller, source) {
+                    warn!("Failed to sync VoiceMeeter changes back to Windows: {err:?}");
+                }
+            }
+        }
+    }
+
+    /// Applies a (coalesced) volume change, along with the dB level and channel volumes the
+    /// notification lacks
+    fn volume_change(&mut self, controller: &mut VoiceMeeterController, mut volume: CurrentVolume) {
+        // Remote volumes don't belong to the local device, there is nothing to query
+        if self.config.use_system_db && !volume.remote {
+            // Notifications only carry the scalar
+            volume.level_db = match self.devices.level_db() {
+                Ok(level_db) => Some(level_db),
+                Err(err) => {
+                    warn!("Failed to get master volume in dB, mapping the scalar instead: {err:?}");
+                    None
+                }
+            };
+        }
+        if self.config.has_channel_targets() && !volume.remote {
+            volume.channel_volumes = match self.devices.channel_volumes() {
+                Ok(channel_volumes) => Some(channel_volumes),
+                Err(err) => {
+                    warn!("Failed to get channel volumes, using the master volume instead: {err:?}");
+                    None
+                }
+            };
+        }
+        handle_volume_change(&self.config, controller, &mut self.state, &mut self.ramp, volume);
+    }
+
+    fn device_change(&mut self, controller: &mut VoiceMeeterController) {
+        handle_device_change(
+            &self.config,
+            controller,
+            &mut self.state,
+            &mut self.ramp,
+            &mut self.settled_sync,
+            &mut self.devices,
+        );
+    }
+
+    /// Brings the targets back after VoiceMeeter was gone, it may have come back as a different
+    /// edition or with the strips reordered
+    fn reconnected(&mut self, controller: &mut VoiceMeeterController) {
+        use_edition_gain_range(&mut self.config, controller);
+        if self.config.targets.iter().any(|target| target.label.is_some()) {
+            let resolved = strip_labels(controller)
+                .and_then(|labels| resolve_labels(&mut self.config.targets, &labels));
+            if let Err(err) = resolved {
+                warn!("Failed to resolve strip labels, keeping the previous strips: {err:?}");
+            }
+        }
+        // A restarted VoiceMeeter loads its own settings, bring the targets back to the current
+        // Windows volume right away instead of on the next volume change
+        self.ramp = None;
+        if self.devices.attached_id().is_some() {
+            if let Err(err) = resync(&self.config, controller, &mut self.state, &self.devices) {
+                warn!("Failed to sync after reconnecting: {err:?}");
+            }
+        }
+    }
+
+    /// Switches to a reloaded config, the device change callback has to be registered again
+    /// with it afterwards
+    fn apply_config(&mut self, controller: &mut VoiceMeeterController, config: Config) {
+        self.config = config;
+        self.ramp = None;
+        let previous = self.devices.attached_id().map(str::to_owned);
+        if self.devices.follow_channels != self.config.has_channel_targets() {
+            self.devices.follow_channels = self.config.has_channel_targets();
+            // Only picked up by a new volume callback
+            if let Err(err) = self.devices.attach(&self.config) {
+                warn!("No device to sync from, waiting for one to appear: {err:?}");
+            }
+        }
+        if self.devices.attached_id() == previous.as_deref() {
+            // A different device may have been configured
+            self.device_change(controller);
+        } else if self.devices.attached_id().is_some() {
+            sync_attached_device(&self.config, controller, &mut self.state, &mut self.settled_sync, &self.devices);
+        }
+        // Switching devices applies the volume once the new one settled, the same device gets its
+        // volume applied again with the new settings right away
+        if previous.is_some() && self.devices.attached_id() == previous.as_deref() {
+            sync_device_volume(&self.config, controller, &mut self.state, &self.devices, None);
+        }
+    }
+
+    /// Attaches to the device again after the callbacks were renewed, catching up with whatever
+    /// the stale callbacks missed
+    fn reattach(&mut self, controller: &mut VoiceMeeterController) {
+        match self.devices.attach(&self.config) {
+            Ok(()) => sync_device_volume(&self.config, controller, &mut self.state, &self.devices, None),
+            Err(err) => warn!("No device to sync from, waiting for one to appear: {err:?}"),
+        }
+    }
+}
+
+/// Syncs the device and targets of each of `configs` until a `ChannelEvent::Shutdown` arrives on
+/// `recv`. All of them share one connection to VoiceMeeter, the options that aren't specific to
+/// a pair are taken from the first one.
+fn run_sync(configs: Vec<Config>, send: Sender<ChannelEvent>, recv: Receiver<ChannelEvent>) -> Result<()> {
+    let config = configs.first().ok_or_else(|| eyre!("nothing to sync"))?.clone();
+    if config.dry_run {
+        warn!("Dry run enabled, nothing will be written to VoiceMeeter!");
+    }
+    let mut controller = VoiceMeeterController::new(config.dry_run, config.retries);
+    if config.wait_for_voicemeeter_secs > 0 && !controller.is_running() {
+        let timeout = config.wait_for_voicemeeter_secs;
+        info!("Waiting up to {timeout}s for VoiceMeeter to start...");
+        let deadline = Instant::now() + Duration::from_secs(timeout);
+        loop {
+            // Nothing else can arrive before the callbacks are registered
+            if let Ok(ChannelEvent::Shutdown) = recv.recv_timeout(STARTUP_POLL_INTERVAL) {
+                return Ok(());
+            }
+            if controller.is_running() {
+                info!("VoiceMeeter is running, starting to sync.");
+                break;
+            }
+            if Instant::now() >= deadline {
+                warn!("VoiceMeeter did not start within {timeout}s, syncing once it does.");
+                break;
+            }
+        }
+    }
+
+    let metrics = Arc::new(Metrics::default());
+    controller.set_metrics(metrics.clone());
+    controller.set_float_epsilon(config.float_epsilon);
+    controller.set_reconnect_hook(reconnect_hook(&config, send.clone(), metrics.clone()));
+    let events = Some(EventCounter::default()).filter(|_| config.verbose_events);
+    let mut pairs = Vec::with_capacity(configs.len());
+    // Do not drop the device change handles, otherwise the event listeners will be unregistered
+    let mut device_change_handles = Vec::with_capacity(configs.len());
+    for pair_config in configs {
+        let devices = Devices {
+            enumerator: device_enumerator()?,
+            pair: pair_config.pair.unwrap_or_default(),
+            send: send.clone(),
+            events: events.clone(),
+            follow_channels: pair_config.has_channel_targets(),
+            metrics: metrics.clone(),
+            source: None,
+        };
+        device_change_handles.push(devices.enumerator.register_endpoint_notification(
+            DeviceChangeCallback::new(&pair_config, send.clone(), events.clone(), metrics.clone()),
+        ));
+        pairs.push(PairSync::start(pair_config, &mut controller, devices)?);
+    }
+    if config.command == Some(Subcommand::Once) {
+        return Ok(());
+    }
+    if pairs.len() > 1 {
+        info!("Syncing {} pairs.", pairs.len());
+    }
+    let tray = if config.tray {
+        match Tray::spawn(send.clone()) {
+            Ok(tray) => Some(tray),
+            Err(err) => {
+                warn!("Failed to create tray icon, continuing without it: {err:?}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let status_server = match config.http_port {
+        Some(port) => match StatusServer::spawn(port) {
+            Ok(server) => Some(server),
+            Err(err) => {
+                warn!("Failed to start status server, continuing without it: {err:?}");
+                None
+            }
+        },
+        None => None,
+    };
+    let mut status_file = config.status_file.clone().map(StatusFile::new);
+    if let Some(name) = &config.control_pipe {
+        if let Err(err) = control::spawn(name, send.clone()) {
+            warn!("Failed to create control pipe, continuing without it: {err:?}");
+        }
+    }
+    if let Some(hotkey) = config.pause_hotkey {
+        if let Err(err) = hotkey::spawn(hotkey, send.clone()) {
+            warn!("Failed to register the pause hotkey, continuing without it: {err:?}");
+        }
+    }
+    if let Err(err) = power::spawn(send.clone()) {
+        warn!("Failed to listen for resume from sleep, syncing may stop after sleep: {err:?}");
+    }
+    if let Some(addr) = &config.listen {
+        if let Err(err) = listen::spawn(addr, send.clone()) {
+            warn!("Failed to listen for volumes, continuing without it: {err:?}");
+        }
+    }
+    if let Err(err) = watch::spawn(config.path.clone(), send.clone()) {
+        warn!("Failed to watch the config file, changes will need a restart: {err:?}");
+    }
+    let mut debounce = Duration::from_millis(config.debounce_ms);
+    // Nothing is written in dry run mode, so every VoiceMeeter value would look like a change
+    let mut reverse_sync_enabled = config.bidirectional && !config.dry_run;
+    let mut next_reverse_sync = Instant::now() + REVERSE_SYNC_INTERVAL;
+    let mut liveness_interval = Duration::from_secs(config.liveness_check_secs);
+    let mut next_liveness_check = Instant::now() + liveness_interval;
+    let mut watchdog = Duration::from_secs(config.watchdog_secs);
+    let mut last_windows_event = Instant::now();
+    let mut next_metrics_log = Instant::now() + METRICS_LOG_INTERVAL;
+    let mut logged_counts = Counts::default();
+    let mut pending = None;
+    loop {
+        // Only wake up periodically if there is something to do in between events
+        let deadline = [
+            Some(Instant::now() + RAMP_STEP_INTERVAL).filter(|_| pairs.iter().any(|pair| pair.ramp.is_some())),
+            Some(next_reverse_sync).filter(|_| {
+                reverse_sync_enabled && pairs.iter().any(|pair| pair.state.voicemeeter_running)
+            }),
+            Some(next_liveness_check).filter(|_| !liveness_interval.is_zero()),
+            Some(last_windows_event + watchdog).filter(|_| !watchdog.is_zero()),
+            Some(next_metrics_log),
+            pairs.iter().filter_map(|pair| pair.settled_sync).min(),
+            status_file.as_ref().and_then(StatusFile::next_write),
+        ].into_iter().flatten().min();
+        let evt = match (pending.take(), deadline) {
+            (Some(evt), _) => evt,
+            (None, Some(deadline)) => match recv.recv_deadline(deadline) {
+                Ok(evt) => evt,
+                Err(RecvTimeoutError::Timeout) => {
+                    let now = Instant::now();
+                    if let Some(status_file) = &mut status_file {
+                        status_file.flush(now);
+                    }
+                    // Reading the dirty flag clears it, so it is read once for all pairs
+                    let mut dirty = false;
+                    if reverse_sync_enabled && now >= next_reverse_sync {
+                        next_reverse_sync = now + REVERSE_SYNC_INTERVAL;
+                        dirty = controller.update_parameters_dirty().unwrap_or_else(|err| {
+                            warn!("Failed to sync VoiceMeeter changes back to Windows: {err:?}");
+                            false
+                        });
+                    }
+                    for pair in &mut pairs {
+                        pair.tick(&mut controller, now, dirty);
+                    }
+                    if !liveness_interval.is_zero() && now >= next_liveness_check {
+                        next_liveness_check = now + liveness_interval;
+                        for pair in &mut pairs {
+                            check_liveness(&mut pair.config, &mut controller, &mut pair.state);
+                        }
+                    }
+                    // Callbacks sometimes stop firing without an error, e.g. after sleep
+                    if !watchdog.is_zero() && now >= last_windows_event + watchdog {
+                        info!("No events from Windows for {}s, registering the callbacks again.", watchdog.as_secs());
+                        last_windows_event = now;
+                        pending = Some(ChannelEvent::RenewCallbacks);
+                    }
+                    if now >= next_metrics_log {
+                        next_metrics_log = now + METRICS_LOG_INTERVAL;
+                        let counts = metrics.counts();
+                        // Nothing worth repeating while idle
+                        if counts != logged_counts {
+                            info!("Since startup: {counts}");
+                            logged_counts = counts;
+                        }
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(eyre!("communication channel disconnected")),
+            },
+            (None, None) => recv.recv().wrap_err("communication channel disconnected")?,
+        };
+        // Remote volumes say nothing about whether the local callbacks still fire
+        let local = matches!(
+            evt,
+            ChannelEvent::VolumeChange(_, CurrentVolume { remote: false, .. }) | ChannelEvent::DeviceChange(_)
+        );
+        if local {
+            last_windows_event = Instant::now();
+        }
         match evt {
-            ChannelEvent::VolumeChange(current_volume) => {
-                if let Err(err) = update_volume(&mut controller, &current_volume) {
-                    warn!("Failed to update current volume: {err:?}");
+            ChannelEvent::VolumeChange(index, mut current_volume) => {
+                // Only apply the most recent volume out of a burst of changes, anything else that
+                // arrives in the meantime is handled right after
+                loop {
+                    let next = if debounce.is_zero() {
+                        recv.try_recv().ok()
+                    } else {
+                        recv.recv_timeout(debounce).ok()
+                    };
+                    match next {
+                        Some(ChannelEvent::VolumeChange(next_index, newer_volume)) if next_index == index => {
+                            current_volume = newer_volume;
+                        }
+                        other => {
+                            pending = other;
+                            break;
+                        }
+                    }
+                }
+                if let Some(pair) = pairs.get_mut(index) {
+                    pair.volume_change(&mut controller, current_volume);
+                }
+            }
+            ChannelEvent::DeviceChange(index) => {
+                if let Some(pair) = pairs.get_mut(index) {
+                    pair.device_change(&mut controller);
+                }
+            }
+            ChannelEvent::Reconnected => {
+                for pair in &mut pairs {
+                    pair.reconnected(&mut controller);
+                }
+            }
+            ChannelEvent::ConfigReload(profile) => {
+                for (pair, handle) in pairs.iter_mut().zip(&mut device_change_handles) {
+                    match reload_config(&pair.config, &mut controller, profile.as_deref()) {
+                        Ok(new_config) => {
+                            let pair_name = new_config.pair.map(|index| format!(" pair {index}")).unwrap_or_default();
+                            match (&profile, new_config.pair) {
+                                (Some(name), _) => info!("Switching{pair_name} to profile {name}."),
+                                (None, Some(index)) => info!("Config file changed, applying it to pair {index}."),
+                                (None, None) => info!("Config file changed, applying the new config."),
+                            }
+                            pair.apply_config(&mut controller, new_config);
+                            // Device notifications are filtered with the settings they were
+                            // registered with
+                            *handle = pair.devices.enumerator.register_endpoint_notification(DeviceChangeCallback::new(
+                                &pair.config,
+                                send.clone(),
+                                pair.devices.events.clone(),
+                                metrics.clone(),
+                            ));
+                        }
+                        Err(err) => warn!("Failed to load the changed config, keeping the previous config: {err:?}"),
+                    }
                 }
+                // Every pair shares these, they come from the first one like at startup
+                let config = &pairs[0].config;
+                controller.set_float_epsilon(config.float_epsilon);
+                debounce = Duration::from_millis(config.debounce_ms);
+                reverse_sync_enabled = config.bidirectional && !config.dry_run;
+                liveness_interval = Duration::from_secs(config.liveness_check_secs);
+                watchdog = Duration::from_secs(config.watchdog_secs);
             }
-            ChannelEvent::DeviceChange => {
-                // Re-attach volume change handle whenever the default device changes
-                _vol_change_handle = setup_volume_cb(&mut controller, &mut enumerator, send.clone());
+            ChannelEvent::Control(request) => {
+                // The control pipe only covers the first pair
+                let pair = &mut pairs[0];
+                let reply = match handle_control(
+                    &request.command,
+                    &mut pair.config,
+                    &mut controller,
+                    &mut pair.state,
+                    &mut pair.original_states,
+                    &pair.devices,
+                ) {
+                    Ok(reply) => {
+                        // Switched like a changed config file, which takes care of everything a
+                        // profile can change
+                        if let ControlCommand::Profile(name) = &request.command {
+                            pending = Some(ChannelEvent::ConfigReload(Some(name.clone())));
+                        }
+                        reply
+                    }
+                    Err(err) => format!("error: {err}"),
+                };
+                if !matches!(request.command, ControlCommand::Status) {
+                    // Whatever was ramping towards is outdated now
+                    pair.ramp = None;
+                }
+                request.reply(reply);
+            }
+            ChannelEvent::TogglePause => {
+                // Like the rest of the controls, pausing only covers the first pair
+                let pair = &mut pairs[0];
+                pair.state.paused = !pair.state.paused;
+                if pair.state.paused {
+                    info!("Paused syncing, the targets keep their current gain.");
+                    pair.ramp = None;
+                } else {
+                    info!("Resumed syncing.");
+                    // Catch up with whatever changed while paused
+                    sync_device_volume(&pair.config, &mut controller, &mut pair.state, &pair.devices, None);
+                }
+            }
+            ChannelEvent::RenewCallbacks => {
+                for (pair, handle) in pairs.iter_mut().zip(&mut device_change_handles) {
+                    // Notifications of the old enumerator may be just as stale, start over with a
+                    // new one and leave it to the supervisor if even that fails
+                    pair.devices.enumerator = device_enumerator().wrap_err("failed to renew the callbacks")?;
+                    *handle = pair.devices.enumerator.register_endpoint_notification(DeviceChangeCallback::new(
+                        &pair.config,
+                        send.clone(),
+                        pair.devices.events.clone(),
+                        metrics.clone(),
+                    ));
+                    pair.reattach(&mut controller);
+                }
             }
+            ChannelEvent::Shutdown => break,
+        }
+        let first = &pairs[0];
+        if let Some(tray) = &tray {
+            tray.set_tooltip(status_text(&first.state));
+        }
+        if let Some(server) = &status_server {
+            server.set_status(status(&first.config, &first.state));
+        }
+        if let Some(status_file) = &mut status_file {
+            status_file.update(status(&first.config, &first.state));
         }
     }
+
+    info!("Shutting down...");
+    // Unregister the callbacks first so no more events come in while we clean up
+    for pair in &mut pairs {
+        pair.devices.source = None;
+    }
+    drop(device_change_handles);
+    for pair in &pairs {
+        for state in &pair.original_states {
+            match state.restore(&pair.config, &mut controller) {
+                Ok(()) => info!("Restored original state of {}", state.target),
+                Err(err) => warn!("Failed to restore original state of {}: {err:?}", state.target),
+            }
+        }
+    }
+    Ok(())
 }
 
-fn setup_volume_cb(
-    controller: &mut VoiceMeeterController,
-    enumerator: &mut DeviceEnumerator,
+/// Forwards reconnects of the controller to the event loop
+fn reconnect_hook(config: &Config, send: Sender<ChannelEvent>, metrics: Arc<Metrics>) -> impl FnMut() + 'static {
+    let notify_reconnect = config.notify;
+    move || {
+        Metrics::count(&metrics.reconnects);
+        if notify_reconnect {
+            notify("Reconnected to VoiceMeeter, syncing again.");
+        }
+        if let Err(e) = send.send(ChannelEvent::Reconnected) {
+            warn!("Failed to send reconnect event: {e:?}");
+        }
+    }
+}
+
+/// Loads the changed config file and checks it against VoiceMeeter. Options that are only used at
+/// startup, like the tray icon or the status server, keep their old behavior until a restart.
+fn reload_config(config: &Config, controller: &mut VoiceMeeterController, profile: Option<&str>) -> Result<Config> {
+    let mut new_config = config.reload(profile)?;
+    // The controller keeps writing (or not) either way
+    new_config.dry_run = config.dry_run;
+    if new_config.targets.iter().any(|target| target.label.is_some()) {
+        let labels = strip_labels(controller)?;
+        resolve_labels(&mut new_config.targets, &labels)?;
+    }
+    validate_targets(&new_config, controller)?;
+    use_edition_gain_range(&mut new_config, controller);
+    Ok(new_config)
+}
+
+/// Switches to the gain range of the running VoiceMeeter edition, unless a range was configured.
+/// Keeps the default range if the edition can't be told yet.
+fn use_edition_gain_range(config: &mut Config, controller: &mut VoiceMeeterController) {
+    // The table is in dB
+    if config.gain_range_set || config.gain_unit != GainUnit::Db {
+        return;
+    }
+    match controller.get_voicemeeter_type() {
+        Ok(application) => if let Some((min_gain, max_gain)) = default_gain_range(application) {
+            info!("Using the {application:?} gain range of {min_gain} to {max_gain} dB");
+            config.min_gain = min_gain;
+            config.max_gain = max_gain;
+        },
+        Err(err) => warn!("Failed to query VoiceMeeter type, using the default gain range: {err:?}"),
+    }
+}
+
+/// Reads the labels of all strips of the running VoiceMeeter edition
+fn strip_labels(controller: &mut VoiceMeeterController) -> Result<Vec<String>> {
+    let application = controller.get_voicemeeter_type()?;
+    // Potato has the most strips, try all of them if the edition is unknown
+    let strips = channel_counts(application).map_or(8, |(strips, _)| strips);
+    (0..strips)
+        .map(|index| controller.get_parameter_string(&SyncTarget::Strip(index).parameter("Label")))
+        .collect()
+}
+
+/// Points the targets picked by label at the strip that currently has that label
+fn resolve_labels(targets: &mut [Target], labels: &[String]) -> Result<()> {
+    for target in targets {
+        let label = match &target.label {
+            Some(label) => label,
+            None => continue,
+        };
+        let index = labels.iter()
+            .position(|found| found.eq_ignore_ascii_case(label))
+            .ok_or_else(|| {
+                let found: Vec<String> = labels.iter().map(|found| format!("\"{found}\"")).collect();
+                eyre!("no strip is labeled \"{label}\", found labels: {}", found.join(", "))
+            })?;
+        target.channel = SyncTarget::Strip(index);
+        info!("{target} is {}", target.channel);
+    }
+    Ok(())
+}
+
+/// Points the targets picked by label at their fallback strip, fails if one has none
+fn use_label_fallbacks(targets: &mut [Target]) -> Result<()> {
+    for target in targets.iter_mut().filter(|target| target.label.is_some()) {
+        let index = target.label_fallback.ok_or_else(|| eyre!(
+            "{target} has no fallback index, add one like label:<label>@<index> to sync without labels"
+        ))?;
+        target.channel = SyncTarget::Strip(index);
+        info!("{target} is {} (fallback)", target.channel);
+    }
+    Ok(())
+}
+
+/// Makes sure all targets exist in the running VoiceMeeter edition. Only warns if VoiceMeeter
+/// isn't running since there is nothing to validate against.
+fn validate_targets(config: &Config, controller: &mut VoiceMeeterController) -> Result<()> {
+    let application = match controller.get_voicemeeter_type() {
+        Ok(application) => application,
+        Err(err) => {
+            warn!("Failed to query VoiceMeeter type, cannot validate targets: {err:?}");
+            return Ok(());
+        }
+    };
+    let (strips, buses) = match channel_counts(application) {
+        Some(counts) => counts,
+        None => {
+            warn!("Unknown VoiceMeeter type {application:?}, cannot validate targets");
+            return Ok(());
+        }
+    };
+    info!("Running VoiceMeeter type: {application:?}");
+    for target in config.all_targets() {
+        match target.channel {
+            SyncTarget::Strip(index) if index >= strips => return Err(eyre!(
+                "{target} does not exist, {application:?} only has strips 0 to {}", strips - 1
+            )),
+            SyncTarget::Bus(index) if index >= buses => return Err(eyre!(
+                "{target} does not exist, {application:?} only has buses 0 to {}", buses - 1
+            )),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Gain and mute of a target as they were before syncing started
+struct TargetState {
+    target: SyncTarget,
+    gain_param: String,
+    mute_param: String,
+    gain: f32,
+    mute: f32,
+}
+
+impl TargetState {
+    fn read(config: &Config, target: SyncTarget, controller: &mut VoiceMeeterController) -> Result<Self> {
+        let gain_param = config.gain_param(target);
+        let mute_param = config.mute_param(target);
+        Ok(Self {
+            target,
+            gain: controller.get_parameter_float(&gain_param)?,
+            mute: controller.get_parameter_float(&mute_param)?,
+            gain_param,
+            mute_param,
+        })
+    }
+
+    fn write(&self, controller: &mut VoiceMeeterController) -> Result<()> {
+        controller.set_parameter_float(&self.gain_param, self.gain)?;
+        controller.set_parameter_float(&self.mute_param, self.mute)?;
+        controller.update_parameters_dirty().map(|_| ())
+    }
+
+    /// Same as `write` but only for the parameters that are synced, the others may have been
+    /// changed by hand in the meantime
+    fn restore(&self, config: &Config, controller: &mut VoiceMeeterController) -> Result<()> {
+        if !config.mute_only {
+            controller.set_parameter_float(&self.gain_param, self.gain)?;
+        }
+        if !config.gain_only {
+            controller.set_parameter_float(&self.mute_param, self.mute)?;
+        }
+        controller.update_parameters_dirty().map(|_| ())
+    }
+}
+
+/// Endpoint the volume is synced from, the volume change callback stays registered for as long as
+/// this is alive
+struct VolumeSource {
+    _callback_handle: AudioEndpointVolumeCallbackHandle,
+    endpoint_volume: AudioEndpointVolume,
+    device_id: String,
+}
+
+impl VolumeSource {
+    fn new(device: Device, callback: VolumeCallback) -> Result<Self> {
+        let device_id = device_id(&device)?;
+        let endpoint_volume = device
+            .activate_audio_endpoint_volume()
+            .wrap_err("failed to activate audio endpoint volume")?;
+        let callback_handle = endpoint_volume
+            .register_control_change_notify(callback)
+            .wrap_err("failed to register volume change notifier")?;
+        Ok(Self {
+            _callback_handle: callback_handle,
+            endpoint_volume,
+            device_id,
+        })
+    }
+}
+
+/// Where the synced volume comes from, implemented by `Devices` so the event handling doesn't
+/// depend on real audio devices
+trait DeviceSource {
+    /// ID of the device the volume is synced from, `None` if there is none
+    fn attached_id(&self) -> Option<&str>;
+
+    /// ID of the device that should be synced from according to the config
+    fn wanted_id(&mut self, config: &Config) -> Result<String>;
+
+    /// Starts syncing from the device that should be synced from, the previous device is detached
+    /// even if this fails
+    fn attach(&mut self, config: &Config) -> Result<()>;
+
+    /// Current volume of the attached device
+    fn volume(&self) -> Result<CurrentVolume>;
+}
+
+/// Audio endpoints known to Windows, along with the one the volume is synced from
+struct Devices {
+    enumerator: DeviceEnumerator,
+    /// Index of the pair the events are tagged with
+    pair: usize,
     send: Sender<ChannelEvent>,
-) -> Result<AudioEndpointVolumeCallbackHandle> {
-    let default_audio_endpoint = enumerator.get_default_audio_endpoint(
-        DataFlow::Render,
-        DeviceRole::Multimedia,
-    ).wrap_err("failed to get default audio endpoint")?;
-    // Update volume once immediately
-    let endpoint_volume = default_audio_endpoint
-        .activate_audio_endpoint_volume()
-        .wrap_err("failed to activate audio endpoint volume")?;
-    if let Err(err) = endpoint_volume.get_master_volume_level_scalar()
-        .wrap_err("failed to get master volume")
-        .and_then(|master| Ok(CurrentVolume {
-            new_volume: master,
-            mute: endpoint_volume.get_mute().wrap_err("failed to get mute status")?
-        }))
-        .and_then(|v| update_volume(controller, &v)) {
+    /// Passed on to the volume change callback
+    events: Option<EventCounter>,
+    follow_channels: bool,
+    metrics: Arc<Metrics>,
+    /// Do not drop the volume source, otherwise the event listener will be unregistered
+    source: Option<VolumeSource>,
+}
+
+impl DeviceSource for Devices {
+    fn attached_id(&self) -> Option<&str> {
+        self.source.as_ref().map(|source| source.device_id.as_str())
+    }
+
+    fn wanted_id(&mut self, config: &Config) -> Result<String> {
+        device_id(&select_device(config, &mut self.enumerator)?)
+    }
+
+    fn attach(&mut self, config: &Config) -> Result<()> {
+        self.source = None;
+        let device = select_device(config, &mut self.enumerator)?;
+        // Only for the log, the device works just as well without a name
+        let name = friendly_name(&device).unwrap_or_else(|err| format!("<unknown: {err}>"));
+        let callback = VolumeCallback {
+            pair: self.pair,
+            send: self.send.clone(),
+            events: self.events.clone(),
+            follow_channels: self.follow_channels,
+            metrics: self.metrics.clone(),
+            last: None,
+        };
+        let source = VolumeSource::new(device, callback)?;
+        info!("Attached to device {name} ({})", source.device_id);
+        self.source = Some(source);
+        Ok(())
+    }
+
+    fn volume(&self) -> Result<CurrentVolume> {
+        let source = self.source.as_ref().ok_or_else(|| eyre!("no device to sync from"))?;
+        read_volume(&source.endpoint_volume)
+    }
+}
+
+impl Devices {
+    /// Master volume of the attached device in dB
+    fn level_db(&self) -> Result<f32> {
+        let source = self.source.as_ref().ok_or_else(|| eyre!("no device to sync from"))?;
+        source.endpoint_volume.get_master_volume_level()
+            .wrap_err("failed to get master volume level")
+    }
+
+    /// Volume scalars of the left and right channel of the attached device
+    fn channel_volumes(&self) -> Result<(f32, f32)> {
+        let source = self.source.as_ref().ok_or_else(|| eyre!("no device to sync from"))?;
+        channel_volumes(&source.endpoint_volume)
+    }
+}
+
+/// Applies a (coalesced) volume change to the targets, ramping towards it if configured
+fn handle_volume_change(
+    config: &Config,
+    controller: &mut dyn ParameterSink,
+    state: &mut SyncState,
+    ramp: &mut Option<Ramp>,
+    volume: CurrentVolume,
+) {
+    if state.paused {
+        debug!("Paused, ignoring volume change to {}", volume.new_volume);
+        return;
+    }
+    let output = match compute_output(config, &volume, state.last_output) {
+        Ok(output) => output,
+        Err(err) => {
+            warn!("Skipping volume change: {err:?}");
+            return;
+        }
+    };
+    write_mappings(config, controller, state, &volume);
+    if let Some(last) = state.last_output {
+        if below_min_change(config, last, output) {
+            debug!("Ignoring gain change from {:.2} dB to {:.2} dB, below the minimum change", last.gain, output.gain);
+            return;
+        }
+    }
+    // A new volume replaces any ramp in progress, starting from wherever it got to. Mute changes
+    // are applied right away since ramping them makes no sense.
+    let write_now = |controller: &mut dyn ParameterSink, state: &mut SyncState| {
+        write_output(config, controller, state, output).unwrap_or_else(|err| {
+            warn!("Failed to update current volume: {err:?}");
+            false
+        })
+    };
+    // Only logged once something was written or a ramp towards it started
+    let mut applied = true;
+    *ramp = match state.last_output {
+        Some(last) if last.muted == output.muted => {
+            let duration = ramp_duration(config, last.gain, output.gain);
+            if duration.is_zero() {
+                applied = write_now(controller, state);
+                None
+            } else {
+                Some(Ramp::new(last.gain, output, duration))
+            }
+        }
+        _ => {
+            applied = write_now(controller, state);
+            None
+        }
+    };
+    if applied {
+        log_output(&volume, output);
+        remember_volume(state, volume);
+    }
+}
+
+/// Whether `output` is too close to the `last` one to be worth writing, mute changes always are
+fn below_min_change(config: &Config, last: Output, output: Output) -> bool {
+    let close = |from: f32, to: f32| (to - from).abs() < config.min_change;
+    let channels_close = match (last.channel_gains, output.channel_gains) {
+        (Some((last_left, last_right)), Some((left, right))) => close(last_left, left) && close(last_right, right),
+        (last_channels, channels) => last_channels.is_none() && channels.is_none(),
+    };
+    config.min_change > 0.0 && last.muted == output.muted && close(last.gain, output.gain) && channels_close
+}
+
+/// How long moving the gain from `from` to `to` should take, the longer of `ramp_ms` and the time
+/// `max_slew` allows
+fn ramp_duration(config: &Config, from: f32, to: f32) -> Duration {
+    let ramp = Duration::from_millis(config.ramp_ms);
+    if config.max_slew == 0.0 {
+        return ramp;
+    }
+    ramp.max(Duration::from_secs_f32((to - from).abs() / config.max_slew))
+}
+
+/// Re-attaches to the device that should be synced from if it changed and applies its volume, right
+/// away or at `settled_sync` once `device_settle_ms` passed. Without a device we just wait for the
+/// next device change to try again.
+fn handle_device_change(
+    config: &Config,
+    controller: &mut dyn ParameterSink,
+    state: &mut SyncState,
+    ramp: &mut Option<Ramp>,
+    settled_sync: &mut Option<Instant>,
+    devices: &mut dyn DeviceSource,
+) {
+    let had_device = devices.attached_id().is_some();
+    let unchanged = match (devices.wanted_id(config), devices.attached_id()) {
+        (Ok(wanted), Some(attached)) => wanted == attached,
+        _ => false,
+    };
+    if unchanged {
+        return;
+    }
+    *ramp = None;
+    *settled_sync = None;
+    match devices.attach(config) {
+        Ok(()) => {
+            // Which device it is was logged when attaching
+            if had_device {
+                info!("Switched to another device.");
+            } else {
+                info!("Found a device to sync from, resuming sync.");
+            }
+            sync_attached_device(config, controller, state, settled_sync, devices);
+        }
+        Err(err) if had_device => warn!("Lost the device to sync from, waiting for one to appear: {err:?}"),
+        Err(err) => debug!("Still no device to sync from: {err:?}"),
+    }
+}
+
+/// Applies the volume of a device that was just attached, or schedules it for `settled_sync` if
+/// `device_settle_ms` is set
+fn sync_attached_device(
+    config: &Config,
+    controller: &mut dyn ParameterSink,
+    state: &mut SyncState,
+    settled_sync: &mut Option<Instant>,
+    devices: &dyn DeviceSource,
+) {
+    // Freshly switched devices may report a volume that is about to change
+    if config.device_settle_ms > 0 {
+        *settled_sync = Some(Instant::now() + Duration::from_millis(config.device_settle_ms));
+    } else {
+        sync_device_volume(config, controller, state, devices, None);
+    }
+}
+
+/// Writes `volume`, or the volume of the attached device if there is none, to the targets
+fn sync_device_volume(
+    config: &Config,
+    controller: &mut dyn ParameterSink,
+    state: &mut SyncState,
+    devices: &dyn DeviceSource,
+    volume: Option<CurrentVolume>,
+) {
+    let volume = match volume {
+        Some(volume) => Ok(volume),
+        None => devices.volume(),
+    };
+    if let Err(err) = volume.and_then(|volume| update_volume(config, controller, state, &volume)) {
         warn!("Failed to update current volume: {err:?}");
     }
-    endpoint_volume
-        .register_control_change_notify(VolumeCallback { send })
-        .wrap_err("failed to register volume change notifier")
 }
 
-fn update_volume(controller: &mut VoiceMeeterController, volume: &CurrentVolume) -> Result<()> {
-    let muted = if volume.new_volume == 0.0 || volume.mute {
+/// Initial sync for `--safe-start`: the targets are muted, moved to the synced gain and only then
+/// given the synced mute state, so whatever gain they had before is never heard
+fn safe_start(
+    config: &Config,
+    controller: &mut dyn ParameterSink,
+    state: &mut SyncState,
+    devices: &dyn DeviceSource,
+    volume: Option<CurrentVolume>,
+) {
+    let volume = match volume {
+        Some(volume) => Ok(volume),
+        None => devices.volume(),
+    };
+    let result = volume.and_then(|volume| {
+        // Nothing to protect against if either the mute or the gain is left alone
+        if state.voicemeeter_running && !config.gain_only && !config.mute_only && gate_open(config, controller)? {
+            let output = compute_output(config, &volume, state.last_output)?;
+            let gains = target_gains(config, output);
+            let muted: Vec<(String, f32)> = gains.iter()
+                .map(|&(target, _)| (config.mute_param(target.channel), 1.0))
+                .collect();
+            let gained: Vec<(String, f32)> = gains.iter()
+                .map(|&(target, gain)| (config.gain_param(target.channel), gain))
+                .collect();
+            for params in [muted, gained] {
+                let params: Vec<(&str, f32)> = params.iter()
+                    .map(|(param, value)| (param.as_str(), *value))
+                    .collect();
+                controller.set_parameters_batch(&params)?;
+            }
+        }
+        // The gain is unchanged by now, this only writes the mute state
+        update_volume(config, controller, state, &volume)
+    });
+    if let Err(err) = result {
+        warn!("Failed to update current volume: {err:?}");
+    }
+}
+
+fn read_volume(endpoint_volume: &AudioEndpointVolume) -> Result<CurrentVolume> {
+    Ok(CurrentVolume {
+        new_volume: endpoint_volume.get_master_volume_level_scalar()
+            .wrap_err("failed to get master volume")?,
+        mute: endpoint_volume.get_mute().wrap_err("failed to get mute status")?,
+        level_db: endpoint_volume.get_master_volume_level().ok(),
+        channel_volumes: channel_volumes(endpoint_volume).ok(),
+        event_id: None,
+        remote: false,
+    })
+}
+
+/// Volume scalars of the first two channels, which are left and right for stereo and surround
+/// devices
+fn channel_volumes(endpoint_volume: &AudioEndpointVolume) -> Result<(f32, f32)> {
+    let count = endpoint_volume.get_channel_count().wrap_err("failed to get channel count")?;
+    ensure!(count >= 2, "device has {count} channel(s), left and right need at least 2");
+    let volume = |channel| endpoint_volume.get_channel_volume_level_scalar(channel)
+        .wrap_err_with(|| format!("failed to get volume of channel {channel}"));
+    Ok((volume(0)?, volume(1)?))
+}
+
+/// Handles a command from the control pipe and returns the reply. `original_states` are the
+/// targets to restore on exit, kept up to date when switching to another strip.
+fn handle_control(
+    command: &ControlCommand,
+    config: &mut Config,
+    controller: &mut VoiceMeeterController,
+    state: &mut SyncState,
+    original_states: &mut Vec<TargetState>,
+    devices: &dyn DeviceSource,
+) -> Result<String> {
+    match command {
+        ControlCommand::Status => Ok(match (state.last_volume, state.last_output) {
+            (Some(volume), Some(output)) => format!(
+                "volume: {:.3}, mute: {}, gain: {:.1}, muted: {}",
+                volume.new_volume, volume.mute, output.gain, output.muted != 0.0
+            ),
+            _ => "nothing synced yet".to_owned(),
+        }),
+        ControlCommand::Resync => {
+            resync(config, controller, state, devices)?;
+            Ok("ok".to_owned())
+        }
+        ControlCommand::SetStrip(index) => {
+            // The channel targets would keep following their strips next to the new one
+            ensure!(!config.has_channel_targets(), "set-strip can't be used while left or right targets are configured");
+            let target = Target {
+                channel: SyncTarget::Strip(*index),
+                label: None,
+                label_fallback: None,
+                offset_db: 0.0,
+            };
+            let previous = std::mem::replace(&mut config.targets, vec![target]);
+            if let Err(err) = validate_targets(config, controller) {
+                config.targets = previous;
+                return Err(err);
+            }
+            // The strips we stop syncing get back what they had before we took them over, the new
+            // one is restored on exit instead
+            let channel = config.targets[0].channel;
+            let (released, kept) = original_states.drain(..).partition(|original| original.target != channel);
+            *original_states = kept;
+            for original in released {
+                match original.restore(config, controller) {
+                    Ok(()) => info!("Restored original state of {}", original.target),
+                    Err(err) => warn!("Failed to restore original state of {}: {err:?}", original.target),
+                }
+            }
+            if original_states.is_empty() {
+                match TargetState::read(config, channel, controller) {
+                    Ok(original) => original_states.push(original),
+                    Err(err) => warn!("Failed to read original state of {channel}, it will not be restored on exit: {err:?}"),
+                }
+            }
+            info!("Syncing to {} now, as requested on the control pipe", config.targets[0]);
+            resync(config, controller, state, devices)?;
+            Ok("ok".to_owned())
+        }
+        // Only checked here, the event loop switches to it right after replying
+        ControlCommand::Profile(name) => {
+            config.check_profile(name)?;
+            Ok("ok".to_owned())
+        }
+    }
+}
+
+/// Writes the current Windows volume to all targets, including values we think are up to date
+fn resync(
+    config: &Config,
+    controller: &mut VoiceMeeterController,
+    state: &mut SyncState,
+    devices: &dyn DeviceSource,
+) -> Result<()> {
+    let volume = devices.volume()?;
+    controller.forget_written();
+    update_volume(config, controller, state, &volume)
+}
+
+/// Mirrors gain changes made in VoiceMeeter back onto the Windows volume, only the first target is
+/// taken into account. Only worth calling once VoiceMeeter reported dirty parameters.
+fn reverse_sync(config: &Config, controller: &mut VoiceMeeterController, source: &VolumeSource) -> Result<()> {
+    let target = &config.targets[0];
+    let param = config.gain_param(target.channel);
+    // Polled often, the liveness check takes care of reconnecting
+    let gain = match controller.try_get_parameter_float(&param)? {
+        Some(gain) => gain,
+        None => return Ok(()),
+    };
+    // Recording the gain as written also makes sure the Windows volume change we are about to
+    // cause does not get written back to VoiceMeeter
+    if !controller.observe_parameter_float(&param, gain) {
+        return Ok(());
+    }
+    if config.use_system_db {
+        source.endpoint_volume
+            .set_master_volume_level(gain - target.offset_db, None)
+            .wrap_err("failed to set master volume level")
+    } else {
+        source.endpoint_volume
+            .set_master_volume_level_scalar(compute_volume(config, from_db(config, gain - target.offset_db)), None)
+            .wrap_err("failed to set master volume")
+    }
+}
+
+/// Mute and gain (before per-target offsets) written to the targets
+#[derive(Clone, Copy)]
+struct Output {
+    muted: f32,
+    gain: f32,
+    /// Gains (before per-target offsets) of the targets following the left and right channel,
+    /// `None` if they follow the master volume like the others
+    channel_gains: Option<(f32, f32)>,
+    /// Whether the targets are muted because the volume is at 0%, which `zero_mute_hysteresis`
+    /// keeps up for a bit after the volume rises again
+    zero_muted: bool,
+    /// Whether the targets are muted because the gain is below `mute_below`, with the same kind of
+    /// hysteresis
+    below_muted: bool,
+}
+
+/// State carried between volume updates
+struct SyncState {
+    /// What was last written to the targets
+    last_output: Option<Output>,
+    /// Whether VoiceMeeter was running at the last liveness check, nothing is written otherwise
+    voicemeeter_running: bool,
+    /// Output that could not be written because VoiceMeeter was not running
+    pending_output: Option<Output>,
+    /// Windows volume the last output was computed from
+    last_volume: Option<CurrentVolume>,
+    /// File the last synced volume is saved to so the next run can start from it
+    volume_file: Option<PathBuf>,
+    /// Whether volume changes are ignored, leaving the targets at their last gain
+    paused: bool,
+    /// `--map` parameters that failed to be written at some point
+    failed_mappings: HashSet<String>,
+}
+
+impl Default for SyncState {
+    fn default() -> Self {
+        Self {
+            last_output: None,
+            voicemeeter_running: true,
+            pending_output: None,
+            last_volume: None,
+            volume_file: None,
+            paused: false,
+            failed_mappings: HashSet::new(),
+        }
+    }
+}
+
+/// Short human readable summary of the sync state, e.g. for the tray tooltip
+fn status_text(state: &SyncState) -> String {
+    let connection = if state.voicemeeter_running {
+        "VoiceMeeter connected"
+    } else {
+        "VoiceMeeter not running"
+    };
+    match state.last_output {
+        _ if state.paused => format!("{APP_NAME}\n{connection}\nPaused"),
+        Some(output) if output.muted != 0.0 => format!("{APP_NAME}\n{connection}\nMuted"),
+        Some(output) => format!("{APP_NAME}\n{connection}\nGain: {:.1} dB", output.gain),
+        None => format!("{APP_NAME}\n{connection}"),
+    }
+}
+
+/// Machine readable summary of the sync state for the status server
+fn status(config: &Config, state: &SyncState) -> Status {
+    Status {
+        connected: state.voicemeeter_running,
+        volume: state.last_volume.map(|volume| volume.new_volume),
+        mute: state.last_volume.map(|volume| volume.mute),
+        gain: state.last_output.map(|output| output.gain),
+        muted: state.last_output.map(|output| output.muted != 0.0),
+        targets: config.all_targets().map(|target| target.channel.to_string()).collect(),
+    }
+}
+
+/// Pauses syncing while VoiceMeeter is not running and catches up once it is back
+fn check_liveness(config: &mut Config, controller: &mut VoiceMeeterController, state: &mut SyncState) {
+    let running = controller.is_running();
+    if running == state.voicemeeter_running {
+        return;
+    }
+    state.voicemeeter_running = running;
+    if running {
+        info!("VoiceMeeter is running again, resuming sync.");
+        use_edition_gain_range(config, controller);
+        if let Some(output) = state.pending_output.take().or(state.last_output) {
+            if let Err(err) = write_output(config, controller, state, output) {
+                warn!("Failed to update current volume: {err:?}");
+            }
+        }
+    } else {
+        info!("VoiceMeeter is not running, waiting for it to come back...");
+    }
+}
+
+/// Gradually moves the gain towards a new value
+struct Ramp {
+    from: f32,
+    to: Output,
+    started: Instant,
+    duration: Duration,
+}
+
+impl Ramp {
+    fn new(from: f32, to: Output, duration: Duration) -> Self {
+        Self {
+            from,
+            to,
+            started: Instant::now(),
+            duration,
+        }
+    }
+
+    /// Returns the interpolated output and whether the ramp is finished
+    fn output_at(&self, now: Instant) -> (Output, bool) {
+        let progress = (now.duration_since(self.started).as_secs_f32() / self.duration.as_secs_f32()).min(1.0);
+        // Only the master volume is ramped, the channels follow right away
+        let output = Output {
+            muted: self.to.muted,
+            gain: self.from + (self.to.gain - self.from) * progress,
+            channel_gains: self.to.channel_gains,
+            zero_muted: self.to.zero_muted,
+            below_muted: self.to.below_muted,
+        };
+        (output, progress >= 1.0)
+    }
+}
+
+fn update_volume(
+    config: &Config,
+    controller: &mut dyn ParameterSink,
+    state: &mut SyncState,
+    volume: &CurrentVolume,
+) -> Result<()> {
+    if state.paused {
+        debug!("Paused, not applying volume {}", volume.new_volume);
+        return Ok(());
+    }
+    let output = compute_output(config, volume, state.last_output)?;
+    if write_output(config, controller, state, output)? {
+        log_output(volume, output);
+        remember_volume(state, *volume);
+    }
+    write_mappings(config, controller, state, volume);
+    Ok(())
+}
+
+/// Logs what a volume was mapped to, along with the event it came from in `--verbose-events` mode
+fn log_output(volume: &CurrentVolume, output: Output) {
+    let event = volume.event_id.map(|id| format!("Event #{id} applied: ")).unwrap_or_default();
+    info!(
+        "{event}volume {:.2} (muted: {}) -> gain {:.1} dB (muted: {})",
+        volume.new_volume,
+        volume.mute,
+        output.gain,
+        output.muted != 0.0
+    );
+}
+
+/// Writes the Windows volume to the `--map` parameters, mapped onto their ranges
+fn write_mappings(config: &Config, controller: &mut dyn ParameterSink, state: &mut SyncState, volume: &CurrentVolume) {
+    if config.mappings.is_empty() || !state.voicemeeter_running {
+        return;
+    }
+    match gate_open(config, controller) {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(err) => {
+            warn!("Failed to update mapped parameters: {err:?}");
+            return;
+        }
+    }
+    let scalar = apply_deadzone(config, volume.new_volume.clamp(0.0, 1.0));
+    let mut written = false;
+    for mapping in &config.mappings {
+        match controller.set_parameter_float_if_changed(&mapping.param, mapping.value(scalar)) {
+            Ok(changed) => written |= changed,
+            // A misspelled parameter fails every time, once is enough to tell
+            Err(err) if state.failed_mappings.insert(mapping.param.clone()) => {
+                warn!("Failed to update {}, further failures are only logged at debug level: {err:?}", mapping.param);
+            }
+            Err(err) => debug!("Failed to update {}: {err:?}", mapping.param),
+        }
+    }
+    if written {
+        if let Err(err) = controller.update_parameters_dirty() {
+            warn!("Failed to update mapped parameters: {err:?}");
+        }
+    }
+}
+
+/// Keeps track of an applied volume and saves it for the next run, failing to save doesn't affect
+/// syncing
+fn remember_volume(state: &mut SyncState, volume: CurrentVolume) {
+    // Notifications often repeat the volume, e.g. for channel changes, the file only has to be
+    // written when what is saved changes
+    let unchanged = matches!(state.last_volume, Some(last) if last.new_volume == volume.new_volume
+        && last.mute == volume.mute
+        && last.level_db == volume.level_db
+        && last.channel_volumes == volume.channel_volumes);
+    state.last_volume = Some(volume);
+    if unchanged {
+        return;
+    }
+    if let Some(path) = &state.volume_file {
+        if let Err(err) = persist::save_volume(path, &volume) {
+            warn!("Failed to save volume: {err:?}");
+        }
+    }
+}
+
+/// Fails instead of producing a gain that makes no sense, endpoints have been seen to report
+/// garbage while devices are being switched
+fn compute_output(config: &Config, volume: &CurrentVolume, last: Option<Output>) -> Result<Output> {
+    ensure!(volume.new_volume.is_finite(), "invalid volume {}", volume.new_volume);
+    let new_volume = volume.new_volume.clamp(0.0, 1.0);
+    if new_volume != volume.new_volume {
+        warn!("Volume {} is out of range, using {new_volume} instead", volume.new_volume);
+    }
+    let new_volume = apply_deadzone(config, new_volume);
+    let (gain, at_floor) = match volume.level_db.filter(|_| config.use_system_db) {
+        // Windows already did the mapping, only VoiceMeeter's range has to be respected
+        Some(level_db) => {
+            let gain = level_db.clamp(MIN_GAIN, MAX_GAIN);
+            (snap_to_step(config, gain, MIN_GAIN, MAX_GAIN), gain <= MIN_GAIN)
+        }
+        None => scalar_gain(config, new_volume),
+    };
+    // Channels are always mapped from the scalar, they only change the gain and leave muting to
+    // the master volume
+    let channel_gains = match volume.channel_volumes.filter(|_| config.has_channel_targets()) {
+        Some((left, right)) => {
+            ensure!(left.is_finite() && right.is_finite(), "invalid channel volumes {left} and {right}");
+            let channel_gain = |volume: f32| scalar_gain(config, apply_deadzone(config, volume.clamp(0.0, 1.0))).0;
+            Some((channel_gain(left), channel_gain(right)))
+        }
+        None => None,
+    };
+    // Zero volume and (optionally) the bottom of the gain range count as muted as well, inverting
+    // applies to all of them. Volumes snapped to zero by the deadzone mute too.
+    // With an inverted mapping 0% is the loudest point, floor mute mutes at 100% instead
+    // Once muted at 0%, the volume has to clear the hysteresis to unmute so a slider resting near
+    // the bottom doesn't keep clicking the mute on and off
+    let was_zero_muted = matches!(last, Some(Output { zero_muted: true, .. }));
+    let zero_threshold = if was_zero_muted { config.zero_mute_hysteresis / 100.0 } else { 0.0 };
+    let at_zero = config.zero_mute && !config.invert && new_volume <= zero_threshold;
+    let at_floor = config.floor_mute && at_floor;
+    // Same for the gain threshold, it has to be cleared by the hysteresis to unmute
+    let below = match config.mute_below {
+        Some(threshold) if matches!(last, Some(Output { below_muted: true, .. })) => {
+            gain <= threshold + config.mute_below_hysteresis
+        }
+        Some(threshold) => gain <= threshold,
+        None => false,
+    };
+    let muted = if (at_zero || at_floor || below || volume.mute) != config.invert_mute {
         1f32
     } else {
         0f32
     };
-    let new_gain = MIN_GAIN + (MAX_GAIN - MIN_GAIN) * volume.new_volume;
-    controller.set_parameter_float("Strip[3].Mute", muted)?;
-    controller.set_parameter_float("Strip[3].Gain", new_gain)?;
-    controller.update_parameters_dirty().map(|_| ())
+    ensure!(gain.is_finite(), "invalid gain {gain} computed from volume {}", volume.new_volume);
+    Ok(Output { muted, gain, channel_gains, zero_muted: at_zero, below_muted: below })
+}
+
+/// Maps a volume scalar onto the gain range, returns the gain in dB and whether it is at the
+/// bottom of the range
+fn scalar_gain(config: &Config, volume: f32) -> (f32, bool) {
+    let (min_gain, max_gain) = gain_range(config);
+    let gain = compute_gain(config, volume).clamp(min_gain, max_gain);
+    let (low, high) = (to_db(config, min_gain), to_db(config, max_gain));
+    (snap_to_step(config, to_db(config, gain), low, high), gain <= min_gain)
+}
+
+/// Range the mapped gain stays within, in `gain_unit`. A curve file brings its own range, which is
+/// only limited by what VoiceMeeter accepts.
+fn gain_range(config: &Config) -> (f32, f32) {
+    match &config.curve_file {
+        Some(table) => {
+            let (low, high) = table.gain_range();
+            (from_db(config, low.max(MIN_GAIN)), from_db(config, high.min(MAX_GAIN)))
+        }
+        None => (config.min_gain, config.max_gain),
+    }
+}
+
+/// Rounds a gain in dB to the nearest multiple of `--gain-step`. The ends of the range are kept
+/// as they are, even if they aren't multiples, and rounding never leaves the range.
+fn snap_to_step(config: &Config, gain: f32, low: f32, high: f32) -> f32 {
+    if config.gain_step == 0.0 || gain <= low || gain >= high {
+        return gain;
+    }
+    ((gain / config.gain_step).round() * config.gain_step).clamp(low, high)
+}
+
+/// Snaps volumes near the ends of the slider to exactly 0.0 or 1.0 so small accidental movements
+/// there don't change the gain
+fn apply_deadzone(config: &Config, volume: f32) -> f32 {
+    let deadzone = config.deadzone / 100.0;
+    if volume <= deadzone {
+        0.0
+    } else if volume >= 1.0 - deadzone {
+        1.0
+    } else {
+        volume
+    }
+}
+
+/// Writes the output to the targets, returns whether anything was actually written
+fn write_output(
+    config: &Config,
+    controller: &mut dyn ParameterSink,
+    state: &mut SyncState,
+    output: Output,
+) -> Result<bool> {
+    if !state.voicemeeter_running {
+        // Applied once VoiceMeeter is back
+        state.pending_output = Some(output);
+        return Ok(false);
+    }
+    if !gate_open(config, controller)? {
+        return Ok(false);
+    }
+    // VoiceMeeter fades the gain by itself if asked to, otherwise it is written along with the mute
+    let gains = target_gains(config, output);
+    let mut faded = false;
+    if config.fade_ms > 0 && !config.mute_only {
+        for &(target, gain) in &gains {
+            match controller.fade_gain_if_changed(&target.channel.to_string(), gain, config.fade_ms) {
+                Ok(written) => faded |= written,
+                Err(err) => warn!("Failed to fade {target}: {err:?}"),
+            }
+        }
+    }
+    let params: Vec<(String, f32)> = gains.iter()
+        .flat_map(|&(target, gain)| {
+            let gain = Some((config.gain_param(target.channel), gain))
+                .filter(|_| config.fade_ms == 0 && !config.mute_only);
+            let mute = Some((config.mute_param(target.channel), output.muted))
+                .filter(|_| !config.gain_only);
+            mute.into_iter().chain(gain)
+        })
+        .collect();
+    let params: Vec<(&str, f32)> = params.iter()
+        .map(|(param, value)| (param.as_str(), *value))
+        .collect();
+    state.last_output = Some(output);
+    // All targets are flushed at once, a single failing target doesn't stop the others
+    let written = controller.set_parameters_batch(&params)?;
+    if !written && faded {
+        controller.update_parameters_dirty()?;
+    }
+    // Fades take a while to arrive and nothing is written in dry run mode
+    if written && config.verify_writes && config.fade_ms == 0 && !config.mute_only && !config.dry_run {
+        verify_gains(config, controller, &gains);
+    }
+    // Macro buttons take effect right away, they don't need to be flushed
+    let mut switched = false;
+    if let Some(button) = config.mute_macro {
+        match controller.set_macro_button_if_changed(button, output.muted > 0.5) {
+            Ok(written) => switched = written,
+            Err(err) => warn!("Failed to update macro button {button}: {err:?}"),
+        }
+    }
+    Ok(written || faded || switched)
+}
+
+/// Reads the gains back after writing them and writes the ones that didn't stick once more, since
+/// VoiceMeeter sometimes drops writes while it is busy
+fn verify_gains(config: &Config, controller: &mut dyn ParameterSink, gains: &[(&Target, f32)]) {
+    for &(target, gain) in gains {
+        let param = config.gain_param(target.channel);
+        let result = controller.get_parameter_float(&param).and_then(|actual| {
+            if nearly_equal(actual, gain, config.float_epsilon) {
+                return Ok(());
+            }
+            warn!("{target} has a gain of {actual} dB instead of {gain} dB, writing it again");
+            controller.set_parameter_float(&param, gain)?;
+            controller.update_parameters_dirty()?;
+            let actual = controller.get_parameter_float(&param)?;
+            ensure!(
+                nearly_equal(actual, gain, config.float_epsilon),
+                "still has a gain of {actual} dB after writing it again"
+            );
+            Ok(())
+        });
+        if let Err(err) = result {
+            warn!("Failed to verify the gain of {target}: {err:?}");
+        }
+    }
+}
+
+/// Whether the `--sync-when` condition allows writing to the targets
+fn gate_open(config: &Config, controller: &mut dyn ParameterSink) -> Result<bool> {
+    if let Some(gate) = &config.sync_when {
+        let value = controller.get_parameter_float(&gate.param)
+            .wrap_err_with(|| format!("failed to check {}", gate.param))?;
+        if !gate.is_met(value, config.float_epsilon) {
+            debug!("Not writing, {} is {value} instead of {}", gate.param, gate.value);
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Every target along with the gain in dB written to it
+fn target_gains(config: &Config, output: Output) -> Vec<(&Target, f32)> {
+    // Without channel volumes the channel targets follow the master volume like the others
+    let (left, right) = output.channel_gains.unwrap_or((output.gain, output.gain));
+    config.targets.iter().map(|target| (target, output.gain))
+        .chain(config.left_targets.iter().map(|target| (target, left)))
+        .chain(config.right_targets.iter().map(|target| (target, right)))
+        .map(|(target, gain)| (target, target_gain(config, target, gain)))
+        .collect()
+}
+
+/// Gain in dB written to a target, the cap also stops offsets from pushing a target above it
+fn target_gain(config: &Config, target: &Target, gain: f32) -> f32 {
+    (gain + target.offset_db)
+        .min(config.gain_cap)
+        .clamp(MIN_GAIN, MAX_GAIN)
+}
+
+/// Converts a gain in the configured unit to dB
+fn to_db(config: &Config, gain: f32) -> f32 {
+    match config.gain_unit {
+        GainUnit::Db => gain,
+        // VoiceMeeter's faders are linear in dB
+        GainUnit::Fader => MIN_GAIN + (MAX_GAIN - MIN_GAIN) * gain / 100.0,
+    }
+}
+
+/// Inverse of `to_db`
+fn from_db(config: &Config, gain: f32) -> f32 {
+    match config.gain_unit {
+        GainUnit::Db => gain,
+        GainUnit::Fader => (gain - MIN_GAIN) / (MAX_GAIN - MIN_GAIN) * 100.0,
+    }
+}
+
+/// Maps a Windows volume scalar (0.0 - 1.0) onto the configured gain range (in `gain_unit`), or
+/// onto the points of the curve file if there is one.
+///
+/// - `linear`: `min + (max - min) * volume`
+/// - `squared`: `min + (max - min) * volume²`, gives finer control near the bottom of the range
+/// - `log`: `max + 20 * log10(volume)` in dB, treats the scalar as an amplitude so 50% is ~6 dB
+///   below the top of the range. Clamped to the range as the result tends to `-inf` near zero.
+///
+/// The curves are computed as a position within the range, which is the same in dB and in fader
+/// units since the faders are linear in dB.
+fn compute_gain(config: &Config, volume: f32) -> f32 {
+    let volume = if config.invert { 1.0 - volume } else { volume };
+    if let Some(table) = &config.curve_file {
+        return from_db(config, table.gain(volume));
+    }
+    let position = match config.curve {
+        Curve::Linear => volume,
+        Curve::Squared => volume * volume,
+        Curve::Log => (1.0 + 20.0 * volume.log10() / db_range(config)).max(0.0),
+    };
+    config.min_gain + (config.max_gain - config.min_gain) * position
 }
 
-const MIN_GAIN: f32 = -30.0;
-const MAX_GAIN: f32 = 12.0;
+/// Size of the configured gain range in dB
+fn db_range(config: &Config) -> f32 {
+    to_db(config, config.max_gain) - to_db(config, config.min_gain)
+}
+
+/// Inverse of `compute_gain`, maps a gain (in `gain_unit`) back onto a Windows volume scalar (0.0 - 1.0)
+fn compute_volume(config: &Config, gain: f32) -> f32 {
+    let (min_gain, max_gain) = gain_range(config);
+    let gain = gain.clamp(min_gain, max_gain);
+    if let Some(table) = &config.curve_file {
+        let volume = table.volume(to_db(config, gain));
+        return if config.invert { 1.0 - volume } else { volume };
+    }
+    let position = (gain - config.min_gain) / (config.max_gain - config.min_gain);
+    let volume = match config.curve {
+        Curve::Linear => position,
+        Curve::Squared => position.sqrt(),
+        Curve::Log => if position <= 0.0 {
+            0.0
+        } else {
+            10f32.powf((position - 1.0) * db_range(config) / 20.0)
+        },
+    };
+    let volume = if config.invert { 1.0 - volume } else { volume };
+    volume.clamp(0.0, 1.0)
+}
 
 struct VolumeCallback {
-    send: Sender<ChannelEvent>
+    /// Index of the pair the events are tagged with
+    pair: usize,
+    send: Sender<ChannelEvent>,
+    events: Option<EventCounter>,
+    /// Whether channel volume changes matter, the notification doesn't tell which volume changed
+    follow_channels: bool,
+    metrics: Arc<Metrics>,
+    /// Master volume and mute of the last notification
+    last: Option<(f32, bool)>,
 }
 impl AudioEndpointVolumeCallback for VolumeCallback {
     fn on_notify(&mut self, data: &NotificationData) -> windows::Result<()> {
-        if let Err(e) =  self.send.send(ChannelEvent::VolumeChange(CurrentVolume {
+        Metrics::count(&self.metrics.volume_events);
+        // Windows sometimes notifies without anything we care about having changed, e.g. for
+        // channel volume changes or just twice in a row
+        let current = (data.master_volume, data.muted);
+        if self.last == Some(current) && !self.follow_channels {
+            return Ok(());
+        }
+        self.last = Some(current);
+        let event_id = self.events.as_ref().map(|events| {
+            events.log(&format!("volume changed to {} (muted: {})", data.master_volume, data.muted))
+        });
+        if let Err(e) =  self.send.send(ChannelEvent::VolumeChange(self.pair, CurrentVolume {
             new_volume: data.master_volume,
             mute: data.muted,
+            level_db: None,
+            channel_volumes: None,
+            event_id,
+            remote: false,
         })) {
             warn!("Failed to send update volume event: {e:?}");
         };
@@ -158,7 +2135,39 @@ impl AudioEndpointVolumeCallback for VolumeCallback {
 }
 
 struct DeviceChangeCallback {
-    send: Sender<ChannelEvent>
+    /// Index of the pair the events are tagged with
+    pair: usize,
+    send: Sender<ChannelEvent>,
+    events: Option<EventCounter>,
+    metrics: Arc<Metrics>,
+    flow: DataFlow,
+    role: DeviceRole,
+    /// Whether only the default device is synced or a specific one was picked by name, either as the
+    /// device to sync from or as the fallback
+    follow_default: bool,
+    /// Whether a device was pinned by ID, which makes the default device irrelevant
+    pinned: bool,
+}
+impl DeviceChangeCallback {
+    fn new(config: &Config, send: Sender<ChannelEvent>, events: Option<EventCounter>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            pair: config.pair.unwrap_or_default(),
+            send,
+            events,
+            metrics,
+            flow: config.flow.data_flow(),
+            role: config.role.device_role(),
+            follow_default: config.device.is_none() && config.fallback_device.is_none() && config.pin_device_id.is_none(),
+            pinned: config.pin_device_id.is_some(),
+        }
+    }
+
+    fn notify(&self) {
+        Metrics::count(&self.metrics.device_changes);
+        if let Err(e) = self.send.send(ChannelEvent::DeviceChange(self.pair)) {
+            warn!("Failed to send device change event: {e:?}");
+        }
+    }
 }
 impl NotificationClient for DeviceChangeCallback {
     fn on_default_device_changed(
@@ -167,11 +2176,270 @@ impl NotificationClient for DeviceChangeCallback {
         role: DeviceRole,
         _: &WinStr,
     ) -> windows::Result<()> {
-        if data_flow == DataFlow::Render && role == DeviceRole::Multimedia {
-            if let Err(e) = self.send.send(ChannelEvent::DeviceChange) {
-                warn!("Failed to send device change event: {e:?}");
+        // The default device also matters when following a named device since it is the fallback
+        // when the named device is missing
+        if data_flow == self.flow && role == self.role && !self.pinned {
+            if let Some(events) = &self.events {
+                events.log("default device changed");
+            }
+            self.notify();
+        }
+        Ok(())
+    }
+
+    fn on_device_state_changed(&mut self, _: &WinStr, _: DeviceState) -> windows::Result<()> {
+        // A named device may have (dis)appeared, the event loop figures out whether it matters
+        if !self.follow_default {
+            if let Some(events) = &self.events {
+                events.log("device state changed");
             }
+            self.notify();
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    /// Keeps the parameters written instead of sending them to VoiceMeeter
+    #[derive(Default)]
+    struct RecordingSink {
+        /// Every write, in order
+        writes: Vec<(String, f32)>,
+        /// Last value written to each parameter
+        values: HashMap<String, f32>,
+    }
+
+    impl RecordingSink {
+        fn value(&self, param: &str) -> Option<f32> {
+            self.values.get(param).copied()
+        }
+    }
+
+    impl ParameterSink for RecordingSink {
+        fn set_parameter_float(&mut self, param: &str, new_value: f32) -> Result<()> {
+            self.writes.push((param.to_owned(), new_value));
+            self.values.insert(param.to_owned(), new_value);
+            Ok(())
+        }
+
+        fn fade_gain_if_changed(&mut self, channel: &str, new_value: f32, _fade_ms: u64) -> Result<bool> {
+            self.set_parameter_float(&format!("{channel}.Gain"), new_value).map(|_| true)
+        }
+
+        fn set_macro_button_if_changed(&mut self, button: usize, state: bool) -> Result<bool> {
+            let state = if state { 1.0 } else { 0.0 };
+            self.set_parameter_float(&format!("Button[{button}].State"), state).map(|_| true)
+        }
+
+        fn update_parameters_dirty(&mut self) -> Result<bool> {
+            Ok(true)
+        }
+
+        fn get_parameter_float(&mut self, param: &str) -> Result<f32> {
+            self.value(param).ok_or_else(|| eyre!("{param} was never written"))
+        }
+    }
+
+    /// Devices along with their volumes, the one to sync from is picked by the test
+    #[derive(Default)]
+    struct ScriptedDevices {
+        volumes: HashMap<&'static str, f32>,
+        /// Device that should be synced from, `None` if there is none
+        wanted: Option<&'static str>,
+        attached: Option<&'static str>,
+    }
+
+    impl DeviceSource for ScriptedDevices {
+        fn attached_id(&self) -> Option<&str> {
+            self.attached
+        }
+
+        fn wanted_id(&mut self, _config: &Config) -> Result<String> {
+            self.wanted.map(str::to_owned).ok_or_else(|| eyre!("no device"))
+        }
+
+        fn attach(&mut self, _config: &Config) -> Result<()> {
+            self.attached = self.wanted;
+            self.attached.map(|_| ()).ok_or_else(|| eyre!("no device"))
+        }
+
+        fn volume(&self) -> Result<CurrentVolume> {
+            let id = self.attached.ok_or_else(|| eyre!("no device"))?;
+            Ok(volume(self.volumes[id], false))
+        }
+    }
+
+    /// Handles `events` the way the event loop does
+    fn handle_events(
+        config: &Config,
+        sink: &mut RecordingSink,
+        state: &mut SyncState,
+        devices: &mut ScriptedDevices,
+        events: impl IntoIterator<Item = ChannelEvent>,
+    ) {
+        let mut ramp = None;
+        let mut settled_sync = None;
+        for event in events {
+            match event {
+                ChannelEvent::VolumeChange(_, volume) => handle_volume_change(config, sink, state, &mut ramp, volume),
+                ChannelEvent::DeviceChange(_) => {
+                    handle_device_change(config, sink, state, &mut ramp, &mut settled_sync, devices);
+                }
+                _ => unreachable!("only volume and device changes are scripted"),
+            }
+        }
+    }
+
+    /// Mute and gain written to the default target
+    fn strip_writes(mute: f32, gain: f32) -> [(String, f32); 2] {
+        [("Strip[3].Mute".to_owned(), mute), ("Strip[3].Gain".to_owned(), gain)]
+    }
+
+    fn volume(new_volume: f32, mute: bool) -> CurrentVolume {
+        CurrentVolume { new_volume, mute, level_db: None, channel_volumes: None, event_id: None, remote: false }
+    }
+
+    /// Syncs a single volume with `config` and returns what was written
+    fn sync(config: &Config, new_volume: f32, mute: bool) -> RecordingSink {
+        let mut sink = RecordingSink::default();
+        update_volume(config, &mut sink, &mut SyncState::default(), &volume(new_volume, mute)).unwrap();
+        sink
+    }
+
+    #[test]
+    fn zero_volume_writes_min_gain_and_mutes() {
+        let config = Config::default();
+        let sink = sync(&config, 0.0, false);
+        assert_eq!(sink.writes, [("Strip[3].Mute".to_owned(), 1.0), ("Strip[3].Gain".to_owned(), config.min_gain)]);
+    }
+
+    #[test]
+    fn full_volume_writes_max_gain() {
+        let config = Config::default();
+        let sink = sync(&config, 1.0, false);
+        assert_eq!(sink.value("Strip[3].Gain"), Some(config.max_gain));
+        assert_eq!(sink.value("Strip[3].Mute"), Some(0.0));
+    }
+
+    #[test]
+    fn half_volume_writes_middle_of_range() {
+        let config = Config::default();
+        let middle = (config.min_gain + config.max_gain) / 2.0;
+        assert_eq!(scalar_gain(&config, 0.5), (middle, false));
+        let sink = sync(&config, 0.5, false);
+        assert_eq!(sink.value("Strip[3].Gain"), Some(middle));
+        assert_eq!(sink.value("Strip[3].Mute"), Some(0.0));
+    }
+
+    #[test]
+    fn zero_volume_without_zero_mute_stays_unmuted() {
+        let mut config = Config::default();
+        config.zero_mute = false;
+        let sink = sync(&config, 0.0, false);
+        assert_eq!(sink.writes, strip_writes(0.0, config.min_gain));
+    }
+
+    #[test]
+    fn muted_volume_mutes_and_keeps_its_gain() {
+        let config = Config::default();
+        let middle = (config.min_gain + config.max_gain) / 2.0;
+        let sink = sync(&config, 0.5, true);
+        assert_eq!(sink.writes, strip_writes(1.0, middle));
+    }
+
+    #[test]
+    fn nan_volume_is_rejected_without_writes() {
+        let mut sink = RecordingSink::default();
+        let mut state = SyncState::default();
+        assert!(update_volume(&Config::default(), &mut sink, &mut state, &volume(f32::NAN, false)).is_err());
+        assert!(sink.writes.is_empty());
+        assert!(state.last_output.is_none());
+    }
+
+    #[test]
+    fn interleaved_volume_and_device_changes() {
+        let config = Config::default();
+        let mut sink = RecordingSink::default();
+        let mut state = SyncState::default();
+        let mut devices = ScriptedDevices {
+            volumes: HashMap::from([("speakers", 0.5), ("headset", 1.0)]),
+            wanted: Some("speakers"),
+            ..ScriptedDevices::default()
+        };
+        // Finding a device applies its volume, later changes are applied as they come
+        handle_events(&config, &mut sink, &mut state, &mut devices, [
+            ChannelEvent::DeviceChange(0),
+            ChannelEvent::VolumeChange(0, volume(0.0, false)),
+        ]);
+        devices.wanted = Some("headset");
+        // Only actually switching devices applies its volume
+        handle_events(&config, &mut sink, &mut state, &mut devices, [
+            ChannelEvent::DeviceChange(0),
+            ChannelEvent::DeviceChange(0),
+            ChannelEvent::VolumeChange(0, volume(0.5, true)),
+        ]);
+        devices.wanted = None;
+        // Losing the device writes nothing, volumes that still arrive are applied
+        handle_events(&config, &mut sink, &mut state, &mut devices, [
+            ChannelEvent::DeviceChange(0),
+            ChannelEvent::VolumeChange(0, volume(1.0, false)),
+        ]);
+        let expected: Vec<(String, f32)> = [
+            strip_writes(0.0, -9.0),
+            strip_writes(1.0, -30.0),
+            strip_writes(0.0, 12.0),
+            strip_writes(1.0, -9.0),
+            strip_writes(0.0, 12.0),
+        ].into_iter().flatten().collect();
+        assert_eq!(sink.writes, expected);
+        assert_eq!(devices.attached, None);
+    }
+
+    #[test]
+    fn device_change_waits_for_the_device_to_settle() {
+        let mut config = Config::default();
+        config.device_settle_ms = 500;
+        let mut sink = RecordingSink::default();
+        let mut state = SyncState::default();
+        let mut devices = ScriptedDevices {
+            volumes: HashMap::from([("speakers", 0.5)]),
+            wanted: Some("speakers"),
+            ..ScriptedDevices::default()
+        };
+        let mut settled_sync = None;
+        handle_device_change(&config, &mut sink, &mut state, &mut None, &mut settled_sync, &mut devices);
+        assert_eq!(devices.attached, Some("speakers"));
+        assert!(settled_sync.is_some());
+        assert!(sink.writes.is_empty());
+        // Volume changes in the meantime are applied as usual
+        let events = [ChannelEvent::VolumeChange(0, volume(1.0, false))];
+        handle_events(&config, &mut sink, &mut state, &mut devices, events);
+        assert_eq!(sink.writes, strip_writes(0.0, 12.0));
+    }
+
+    #[test]
+    fn log_curve_in_fader_units_matches_db() {
+        let mut config = Config::default();
+        config.curve = Curve::Log;
+        config.gain_unit = GainUnit::Fader;
+        config.min_gain = 0.0;
+        config.max_gain = 100.0;
+        let gain = compute_gain(&config, 0.5);
+        assert!(nearly_equal(to_db(&config, gain), MAX_GAIN + 20.0 * 0.5f32.log10(), 0.001));
+        assert!(nearly_equal(compute_volume(&config, gain), 0.5, 0.001));
+        assert_eq!(compute_gain(&config, 0.0), 0.0);
+    }
+
+    #[test]
+    fn autostart_arguments_survive_command_line_splitting() {
+        assert_eq!(autostart::quote_arg("--verbose"), "--verbose");
+        assert_eq!(autostart::quote_arg(""), "\"\"");
+        assert_eq!(autostart::quote_arg("C:\\my dir\\"), "\"C:\\my dir\\\\\"");
+        assert_eq!(autostart::quote_arg("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(autostart::quote_arg("a\\\"b"), "\"a\\\\\\\"b\"");
+        assert_eq!(autostart::quote_arg("tab\there"), "\"tab\there\"");
+    }
+}
@@ -0,0 +1,49 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How much work the sync did since startup, updated from the callbacks and the controller
+#[derive(Default)]
+pub struct Metrics {
+    /// Volume change notifications received from Windows
+    pub volume_events: AtomicU64,
+    /// Device change notifications received from Windows
+    pub device_changes: AtomicU64,
+    /// Parameters written to VoiceMeeter
+    pub writes: AtomicU64,
+    /// Times VoiceMeeter was connected again after the connection was lost
+    pub reconnects: AtomicU64,
+}
+
+impl Metrics {
+    pub fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counts(&self) -> Counts {
+        Counts {
+            volume_events: self.volume_events.load(Ordering::Relaxed),
+            device_changes: self.device_changes.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Values of the `Metrics` counters at some point in time
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub volume_events: u64,
+    pub device_changes: u64,
+    pub writes: u64,
+    pub reconnects: u64,
+}
+
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} volume events, {} device changes, {} writes, {} reconnects",
+            self.volume_events, self.device_changes, self.writes, self.reconnects
+        )
+    }
+}
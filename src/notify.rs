@@ -0,0 +1,14 @@
+use log::warn;
+use notify_rust::Notification;
+use crate::APP_NAME;
+
+/// Shows a desktop notification, failures are only logged since notifications are best effort
+pub fn notify(body: &str) {
+    if let Err(err) = Notification::new()
+        .appname(APP_NAME)
+        .summary(APP_NAME)
+        .body(body)
+        .show() {
+        warn!("Failed to show notification: {err:?}");
+    }
+}
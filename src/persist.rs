@@ -0,0 +1,40 @@
+use std::{env, fs};
+use std::path::{Path, PathBuf};
+use eyre::{Result, WrapErr};
+use log::warn;
+use crate::CurrentVolume;
+
+const VOLUME_FILE_NAME: &str = "last_volume.toml";
+
+/// Location of the file the last synced volume is kept in, next to the executable. Each pair of
+/// the config file gets its own.
+pub fn volume_file(pair: Option<usize>) -> Result<PathBuf> {
+    let exe = env::current_exe().wrap_err("failed to locate executable")?;
+    Ok(match pair {
+        Some(index) => exe.with_file_name(format!("last_volume_{index}.toml")),
+        None => exe.with_file_name(VOLUME_FILE_NAME),
+    })
+}
+
+/// Reads the volume saved by `save_volume`, `None` if there is none or it can't be used
+pub fn load_volume(path: &Path) -> Option<CurrentVolume> {
+    // Not having synced anything yet is normal, no need to warn about that
+    let contents = fs::read_to_string(path).ok()?;
+    match toml::from_str::<CurrentVolume>(&contents) {
+        Ok(volume) if (0.0..=1.0).contains(&volume.new_volume) => Some(volume),
+        Ok(volume) => {
+            warn!("Ignoring out of range volume {} in {}", volume.new_volume, path.display());
+            None
+        }
+        Err(err) => {
+            warn!("Ignoring unreadable volume file {}: {err:?}", path.display());
+            None
+        }
+    }
+}
+
+pub fn save_volume(path: &Path, volume: &CurrentVolume) -> Result<()> {
+    let contents = toml::to_string(volume).wrap_err("failed to serialize volume")?;
+    fs::write(path, contents)
+        .wrap_err_with(|| format!("failed to write volume file {}", path.display()))
+}
@@ -0,0 +1,90 @@
+use std::cell::RefCell;
+use std::ptr;
+use std::thread;
+use crossbeam::channel::{bounded, Sender};
+use eyre::{eyre, Result, WrapErr};
+use log::info;
+use windows_sys::Win32::Foundation::{GetLastError, ERROR_CLASS_ALREADY_EXISTS, HWND, LPARAM, LRESULT, WPARAM};
+use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows_sys::Win32::UI::WindowsAndMessaging::{CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, PostQuitMessage, RegisterClassW, MSG, PBT_APMRESUMEAUTOMATIC, WM_POWERBROADCAST, WNDCLASSW};
+use crate::ChannelEvent;
+use crate::win::to_wide;
+
+const WINDOW_CLASS: &str = "VoiceMeeterVolumeSyncPower";
+
+thread_local! {
+    /// Where the window of this thread sends resume notifications, the window procedure has no
+    /// other way to get at it
+    static SEND: RefCell<Option<Sender<ChannelEvent>>> = RefCell::new(None);
+}
+
+/// Sends `ChannelEvent::RenewCallbacks` whenever Windows resumes from sleep, since volume and
+/// device notifications often stop arriving afterwards. Power notifications are only broadcast to
+/// top level windows, so a hidden one is created on a dedicated thread.
+pub fn spawn(send: Sender<ChannelEvent>) -> Result<()> {
+    let (created_send, created) = bounded(1);
+    thread::Builder::new()
+        .name("power".to_owned())
+        .spawn(move || {
+            SEND.with(|cell| *cell.borrow_mut() = Some(send));
+            if let Err(err) = create_window() {
+                let _ = created_send.send(Err(err));
+                return;
+            }
+            let _ = created_send.send(Ok(()));
+            // SAFETY: MSG is plain data, all zeroes is a valid value
+            let mut msg: MSG = unsafe { std::mem::zeroed() };
+            // SAFETY: msg outlives the calls, 0 and -1 mean quit and failure
+            while unsafe { GetMessageW(&mut msg, 0, 0, 0) } > 0 {
+                unsafe { DispatchMessageW(&msg) };
+            }
+        })
+        .wrap_err("failed to spawn power notification thread")?;
+    created.recv().wrap_err("power notification thread stopped before creating its window")?
+}
+
+fn create_window() -> Result<()> {
+    let class_name = to_wide(WINDOW_CLASS);
+    // SAFETY: no module name means the executable itself
+    let instance = unsafe { GetModuleHandleW(ptr::null()) };
+    let class = WNDCLASSW {
+        lpfnWndProc: Some(window_proc),
+        hInstance: instance,
+        lpszClassName: class_name.as_ptr(),
+        // SAFETY: the remaining fields are optional, zero means none
+        ..unsafe { std::mem::zeroed() }
+    };
+    // SAFETY: class_name outlives the call. A class that is already registered works just as well.
+    if unsafe { RegisterClassW(&class) } == 0 {
+        let error = unsafe { GetLastError() };
+        if error != ERROR_CLASS_ALREADY_EXISTS {
+            return Err(eyre!("failed to register power notification window class, error {error}"));
+        }
+    }
+    // SAFETY: the class is registered, the window is never shown
+    let window = unsafe {
+        CreateWindowExW(0, class_name.as_ptr(), class_name.as_ptr(), 0, 0, 0, 0, 0, 0, 0, instance, ptr::null())
+    };
+    if window == 0 {
+        let error = unsafe { GetLastError() };
+        return Err(eyre!("failed to create power notification window, error {error}"));
+    }
+    Ok(())
+}
+
+unsafe extern "system" fn window_proc(window: HWND, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    // Sent on every resume, the one for resumes triggered by the user only follows it
+    if message == WM_POWERBROADCAST && wparam == PBT_APMRESUMEAUTOMATIC as WPARAM {
+        info!("Resumed from sleep, registering the callbacks again.");
+        let sent = SEND.with(|send| match &*send.borrow() {
+            Some(send) => send.send(ChannelEvent::RenewCallbacks).is_ok(),
+            None => false,
+        });
+        // Nobody left to renew the callbacks if the channel is gone
+        if !sent {
+            PostQuitMessage(0);
+        }
+        return 1;
+    }
+    DefWindowProcW(window, message, wparam, lparam)
+}
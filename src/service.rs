@@ -0,0 +1,132 @@
+use std::env;
+use std::ffi::OsString;
+use std::time::Duration;
+use crossbeam::channel::{Receiver, Sender, unbounded};
+use eyre::{eyre, Result, WrapErr};
+use log::{error, info, warn};
+use windows_service::define_windows_service;
+use windows_service::service::{ServiceAccess, ServiceAction, ServiceActionType, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceFailureActions, ServiceFailureResetPeriod, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle};
+use windows_service::service_dispatcher;
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use crate::config::Config;
+use crate::instance::InstanceGuard;
+use crate::{APP_NAME, AlreadyRunning, ChannelEvent};
+
+const SERVICE_NAME: &str = APP_NAME;
+const SERVICE_DISPLAY_NAME: &str = "VoiceMeeter Volume Sync";
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+/// Same delay as the supervisor uses in console mode
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Registers the service to start with Windows, passing `args` along with `--run-as-service`
+pub fn install(args: &[String]) -> Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    ).wrap_err("failed to connect to the service manager, try again as administrator")?;
+    let mut launch_arguments = vec![OsString::from("--run-as-service")];
+    launch_arguments.extend(args.iter().map(OsString::from));
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from(SERVICE_DISPLAY_NAME),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: env::current_exe().wrap_err("failed to locate executable")?,
+        launch_arguments,
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .wrap_err("failed to create service")?;
+    service.set_description("Keeps VoiceMeeter in sync with the Windows volume")
+        .wrap_err("failed to set service description")?;
+    // The VoiceMeeter SDK takes the whole process down when VoiceMeeter goes away, let the service
+    // manager take over the supervisor's job of restarting it
+    service.update_failure_actions(ServiceFailureActions {
+        reset_period: ServiceFailureResetPeriod::After(Duration::from_secs(24 * 60 * 60)),
+        reboot_msg: None,
+        command: None,
+        actions: Some(vec![ServiceAction { action_type: ServiceActionType::Restart, delay: RESTART_DELAY }]),
+    }).wrap_err("failed to set service failure actions")
+}
+
+pub fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .wrap_err("failed to connect to the service manager, try again as administrator")?;
+    manager.open_service(SERVICE_NAME, ServiceAccess::DELETE)
+        .wrap_err("failed to open service, is it installed?")?
+        .delete()
+        .wrap_err("failed to delete service")
+}
+
+/// Hands control to the service manager, returns once the service has stopped
+pub fn run() -> Result<()> {
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .wrap_err("failed to start service, --run-as-service only works when launched by the service manager")
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(err) = run_service() {
+        error!("Service failed: {err:?}");
+    }
+}
+
+fn run_service() -> Result<()> {
+    let (send, recv) = unbounded::<ChannelEvent>();
+    let stop_send = send.clone();
+    // Registered before anything can fail, otherwise the service manager only sees a service that
+    // never reported back instead of one that stopped
+    let status_handle = service_control_handler::register(SERVICE_NAME, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            if let Err(e) = stop_send.send(ChannelEvent::Shutdown) {
+                warn!("Failed to send shutdown event: {e:?}");
+            }
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    }).wrap_err("failed to register service control handler")?;
+
+    let result = sync(&status_handle, send, recv);
+    // Reporting the stop keeps the restart action from relaunching a config that can't work
+    let exit_code = match &result {
+        Ok(()) => ServiceExitCode::Win32(0),
+        Err(err) => ServiceExitCode::ServiceSpecific(crate::exit_code(err) as u32),
+    };
+    set_status(&status_handle, ServiceState::Stopped, exit_code)?;
+    result
+}
+
+fn sync(status_handle: &ServiceStatusHandle, send: Sender<ChannelEvent>, recv: Receiver<ChannelEvent>) -> Result<()> {
+    // The arguments passed to the service main are the ones from the service start request, the
+    // configured ones are on the command line
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config = Config::load(&args).wrap_err("failed to load config")?;
+    let _instance_guard = InstanceGuard::acquire(APP_NAME)?
+        .ok_or_else(|| eyre!(AlreadyRunning))?;
+
+    set_status(status_handle, ServiceState::Running, ServiceExitCode::Win32(0))?;
+    info!("Running as a service.");
+    crate::run(config, send, recv)
+}
+
+fn set_status(handle: &ServiceStatusHandle, state: ServiceState, exit_code: ServiceExitCode) -> Result<()> {
+    let controls_accepted = match state {
+        ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        _ => ServiceControlAccept::empty(),
+    };
+    handle.set_service_status(ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: state,
+        controls_accepted,
+        exit_code,
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }).wrap_err("failed to update service status")
+}
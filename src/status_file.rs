@@ -0,0 +1,90 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use eyre::{Result, WrapErr};
+use log::warn;
+use serde::Serialize;
+use crate::http::Status;
+
+/// Writes happen at most this often, e.g. while a slider is being dragged
+const WRITE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What ends up in the file, the status along with when it was written
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(flatten)]
+    status: &'a Status,
+    timestamp: String,
+}
+
+/// Keeps a JSON file up to date with the sync status, for overlays and scripts that would rather
+/// read a file than talk to the status server
+pub struct StatusFile {
+    path: PathBuf,
+    /// Status in the file, `None` until the first write
+    written: Option<Status>,
+    last_write: Option<Instant>,
+    /// Status that changed too soon after the last write, written by `flush`
+    pending: Option<Status>,
+}
+
+impl StatusFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, written: None, last_write: None, pending: None }
+    }
+
+    /// Writes `status` if it changed, or remembers it for `flush` if the last write was too recent
+    pub fn update(&mut self, status: Status) {
+        if self.written.as_ref() == Some(&status) {
+            self.pending = None;
+            return;
+        }
+        self.pending = Some(status);
+        self.flush(Instant::now());
+    }
+
+    /// When a pending status is due to be written, `None` if there is none
+    pub fn next_write(&self) -> Option<Instant> {
+        if self.pending.is_none() {
+            return None;
+        }
+        Some(match self.last_write {
+            Some(last_write) => last_write + WRITE_INTERVAL,
+            None => Instant::now(),
+        })
+    }
+
+    /// Writes the pending status if it is due at `now`
+    pub fn flush(&mut self, now: Instant) {
+        let due = match self.last_write {
+            Some(last_write) => now >= last_write + WRITE_INTERVAL,
+            None => true,
+        };
+        if !due {
+            return;
+        }
+        if let Some(status) = self.pending.take() {
+            // Failing to write is not worth stopping the sync over, the next change tries again
+            if let Err(err) = self.write(&status) {
+                warn!("Failed to write status file: {err:?}");
+            }
+            self.last_write = Some(now);
+            self.written = Some(status);
+        }
+    }
+
+    /// Writes to a temporary file first and moves it over the status file, so readers never see
+    /// a partially written file
+    fn write(&self, status: &Status) -> Result<()> {
+        let record = Record { status, timestamp: chrono::Local::now().to_rfc3339() };
+        let json = serde_json::to_string(&record).wrap_err("failed to serialize status")?;
+        let mut temp_path = OsString::from(self.path.as_os_str());
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        fs::write(&temp_path, json)
+            .wrap_err_with(|| format!("failed to write {}", temp_path.display()))?;
+        fs::rename(&temp_path, &self.path)
+            .wrap_err_with(|| format!("failed to replace {}", self.path.display()))
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use crossbeam::channel::Sender;
+use eyre::{eyre, Result, WrapErr};
+use log::warn;
+use trayicon::{MenuBuilder, TrayIconBuilder};
+use windows_sys::Win32::System::Threading::GetCurrentThreadId;
+use windows_sys::Win32::UI::WindowsAndMessaging::{DispatchMessageW, GetMessageW, PostThreadMessageW, TranslateMessage, MSG, WM_APP, WM_QUIT};
+use crate::{APP_NAME, ChannelEvent};
+
+const ICON: &[u8] = include_bytes!("../assets/icon.ico");
+/// Posted to the tray thread whenever the tooltip changed
+const WM_UPDATE_TOOLTIP: u32 = WM_APP + 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TrayEvent {
+    Quit,
+}
+
+/// Tray icon running its own message loop on a separate thread, removed when dropped
+pub struct Tray {
+    thread_id: u32,
+    tooltip: Arc<Mutex<String>>,
+}
+
+impl Tray {
+    pub fn spawn(send: Sender<ChannelEvent>) -> Result<Self> {
+        let tooltip = Arc::new(Mutex::new(APP_NAME.to_owned()));
+        let thread_tooltip = tooltip.clone();
+        let (started_send, started_recv) = mpsc::channel();
+        thread::Builder::new()
+            .name("tray".to_owned())
+            .spawn(move || run(send, thread_tooltip, started_send))
+            .wrap_err("failed to spawn tray thread")?;
+        let thread_id = started_recv.recv().wrap_err("tray thread exited unexpectedly")??;
+        Ok(Self { thread_id, tooltip })
+    }
+
+    pub fn set_tooltip(&self, tooltip: String) {
+        *self.tooltip.lock().unwrap() = tooltip;
+        // SAFETY: posting a message without pointers to a thread that may or may not still exist is fine
+        unsafe { PostThreadMessageW(self.thread_id, WM_UPDATE_TOOLTIP, 0, 0) };
+    }
+}
+
+impl Drop for Tray {
+    fn drop(&mut self) {
+        unsafe { PostThreadMessageW(self.thread_id, WM_QUIT, 0, 0) };
+    }
+}
+
+fn run(send: Sender<ChannelEvent>, tooltip: Arc<Mutex<String>>, started: mpsc::Sender<Result<u32>>) {
+    let (tray_send, tray_recv) = mpsc::channel();
+    let tray_icon = TrayIconBuilder::new()
+        .sender(tray_send)
+        .icon_from_buffer(ICON)
+        .tooltip(&tooltip.lock().unwrap())
+        .menu(MenuBuilder::new().item("Quit", TrayEvent::Quit))
+        .build();
+    let mut tray_icon = match tray_icon {
+        Ok(tray_icon) => tray_icon,
+        Err(err) => {
+            let _ = started.send(Err(eyre!("failed to create tray icon: {err:?}")));
+            return;
+        }
+    };
+    // Creating the icon also created this thread's message queue, so it can receive messages now
+    let _ = started.send(Ok(unsafe { GetCurrentThreadId() }));
+
+    // SAFETY: MSG is plain old data
+    let mut msg: MSG = unsafe { std::mem::zeroed() };
+    while unsafe { GetMessageW(&mut msg, 0, 0, 0) } > 0 {
+        if msg.message == WM_UPDATE_TOOLTIP {
+            if let Err(err) = tray_icon.set_tooltip(&tooltip.lock().unwrap()) {
+                warn!("Failed to update tray tooltip: {err:?}");
+            }
+        }
+        unsafe {
+            TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+        // Menu clicks are delivered while dispatching
+        while let Ok(event) = tray_recv.try_recv() {
+            match event {
+                TrayEvent::Quit => if let Err(e) = send.send(ChannelEvent::Shutdown) {
+                    warn!("Failed to send shutdown event: {e:?}");
+                },
+            }
+        }
+    }
+}
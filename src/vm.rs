@@ -1,11 +1,104 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use voicemeeter::VoicemeeterRemote;
-use eyre::{Result, WrapErr};
-use log::warn;
-use voicemeeter::interface::general_information::VoicemeeterVersion;
-use voicemeeter::types::ParameterNameRef;
+use eyre::{eyre, Result, WrapErr};
+use log::{info, warn};
+use voicemeeter::types::{ParameterNameRef, VoicemeeterApplication};
+use crate::metrics::Metrics;
+
+/// Range of gains (in dB) accepted by strips and buses
+pub const MIN_GAIN: f32 = -60.0;
+pub const MAX_GAIN: f32 = 12.0;
+
+/// Delay before the second reconnect attempt, doubled after every further failure
+const RECONNECT_BACKOFF_START: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// Pause between retries of a call that failed right after reconnecting
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Gains closer than this are considered the same unless configured otherwise, in dB
+pub const DEFAULT_FLOAT_EPSILON: f32 = 0.01;
+
+/// Whether two parameter values are the same as far as syncing is concerned
+pub fn nearly_equal(a: f32, b: f32, epsilon: f32) -> bool {
+    (a - b).abs() < epsilon
+}
+
+fn log_version(remote: &VoicemeeterRemote) {
+    match remote.get_voicemeeter_version() {
+        Ok(version) => info!("Connected to VoiceMeeter version {version:?}"),
+        Err(err) => warn!("Failed to get VoiceMeeter version: {err:?}"),
+    }
+}
+
+/// Destination of the parameter writes made while syncing, implemented by `VoiceMeeterController`
+/// so the mapping logic doesn't depend on a running VoiceMeeter
+pub trait ParameterSink {
+    fn set_parameter_float(&mut self, param: &str, new_value: f32) -> Result<()>;
+
+    /// Same as `set_parameter_float` but may skip writes of unchanged values, returns whether the
+    /// parameter was written
+    fn set_parameter_float_if_changed(&mut self, param: &str, new_value: f32) -> Result<bool> {
+        self.set_parameter_float(param, new_value).map(|_| true)
+    }
+
+    /// Fades the gain of a strip or bus (e.g. `Strip[3]`) to `new_value` over `fade_ms`, may skip
+    /// fades to unchanged values, returns whether the fade was started
+    fn fade_gain_if_changed(&mut self, channel: &str, new_value: f32, fade_ms: u64) -> Result<bool>;
+
+    /// Switches a macro button on or off, may skip unchanged states, returns whether the button was
+    /// switched
+    fn set_macro_button_if_changed(&mut self, button: usize, state: bool) -> Result<bool>;
+
+    fn update_parameters_dirty(&mut self) -> Result<bool>;
+
+    /// Reads a parameter, e.g. to check whether the sync should write at all
+    fn get_parameter_float(&mut self, param: &str) -> Result<f32>;
+
+    /// Writes all changed parameters and flushes them at once, returns whether anything was
+    /// written. A failed write is logged and doesn't stop the others, only a failed flush is
+    /// returned as an error.
+    fn set_parameters_batch(&mut self, params: &[(&str, f32)]) -> Result<bool> {
+        let mut changed = false;
+        for (param, value) in params {
+            match self.set_parameter_float_if_changed(param, *value) {
+                Ok(written) => changed |= written,
+                Err(err) => warn!("Failed to update {param}: {err:?}"),
+            }
+        }
+        if changed {
+            self.update_parameters_dirty()?;
+        }
+        Ok(changed)
+    }
+}
 
 pub struct VoiceMeeterController {
-    inner: Option<VoiceMeeterControllerInner>
+    inner: Option<VoiceMeeterControllerInner>,
+    /// Log parameter writes instead of performing them
+    dry_run: bool,
+    /// How many times writes are retried with a fresh connection before giving up
+    retries: u32,
+    /// Last value written to each float parameter on the current connection
+    written: HashMap<String, f32>,
+    /// Reconnect attempts that failed in a row
+    failed_reconnects: u32,
+    /// No reconnect is attempted before this point in time
+    next_reconnect: Option<Instant>,
+    /// Whether a connection was established at some point, used to tell reconnects apart from
+    /// the initial connection
+    connected_before: bool,
+    /// Whether `is_running` last reported that VoiceMeeter is not running
+    reported_down: bool,
+    /// Called whenever VoiceMeeter is available again after the connection was lost
+    on_reconnect: Option<Box<dyn FnMut()>>,
+    /// Counts the parameter writes
+    metrics: Option<Arc<Metrics>>,
+    /// Float parameter writes closer than this to the previously written value are skipped
+    float_epsilon: f32,
 }
 
 struct VoiceMeeterControllerInner {
@@ -13,7 +106,7 @@ struct VoiceMeeterControllerInner {
 }
 
 impl VoiceMeeterController {
-    pub fn new() -> Self {
+    pub fn new(dry_run: bool, retries: u32) -> Self {
         let vm = match VoicemeeterRemote::new() {
             Ok(v) => Some(VoiceMeeterControllerInner { remote: v }),
             Err(err) => {
@@ -21,50 +114,265 @@ impl VoiceMeeterController {
                 None
             }
         };
-        Self {
+        let mut controller = Self {
+            connected_before: vm.is_some(),
             inner: vm,
+            dry_run,
+            retries,
+            written: HashMap::new(),
+            failed_reconnects: 0,
+            next_reconnect: None,
+            reported_down: false,
+            on_reconnect: None,
+            metrics: None,
+            float_epsilon: DEFAULT_FLOAT_EPSILON,
+        };
+        controller.query_version();
+        controller
+    }
+
+    pub fn set_reconnect_hook(&mut self, hook: impl FnMut() + 'static) {
+        self.on_reconnect = Some(Box::new(hook));
+    }
+
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    pub fn set_float_epsilon(&mut self, epsilon: f32) {
+        self.float_epsilon = epsilon;
+    }
+
+    fn count_write(&self) {
+        if let Some(metrics) = &self.metrics {
+            Metrics::count(&metrics.writes);
+        }
+    }
+
+    /// Logs the version of the VoiceMeeter we are connected to, a failure is only logged
+    fn query_version(&self) {
+        if let Some(inner) = &self.inner {
+            log_version(&inner.remote);
         }
     }
 
-    fn retry_once<T>(&mut self, mut block: impl FnMut(&mut VoiceMeeterControllerInner) -> Result<T>) -> Result<T> {
+    fn reconnected(&mut self) {
+        self.reported_down = false;
+        if let Some(hook) = &mut self.on_reconnect {
+            hook();
+        }
+    }
+
+    fn retry_once<T>(&mut self, block: impl FnMut(&mut VoiceMeeterControllerInner) -> Result<T>) -> Result<T> {
+        self.retry(1, block)
+    }
+
+    /// Calls block, reconnecting and calling it again up to `times` times if it fails
+    fn retry<T>(&mut self, times: u32, mut block: impl FnMut(&mut VoiceMeeterControllerInner) -> Result<T>) -> Result<T> {
         // If connected, call block
         let result = self.inner
             .as_mut()
             .and_then(|x| block(x).ok());
-        match result {
-            Some(v) => Ok(v),
-            None => {
-                // Not connected or block call failed, reconnect
-                warn!("VoiceMeeter call failed or VoiceMeeter is not connected, re-connecting now...");
-                let mut vm = VoiceMeeterControllerInner {
-                    remote: VoicemeeterRemote::new().wrap_err("failed to connect to VoiceMeeter")?
-                };
-                let call_result = block(&mut vm);
-                self.inner = Some(vm);
-                call_result
+        if let Some(v) = result {
+            return Ok(v);
+        }
+        let mut attempt = 1;
+        loop {
+            match self.reconnect_and_call(&mut block) {
+                Ok(v) => return Ok(v),
+                // No point in retrying before the reconnect backoff is over
+                Err(err) if attempt >= times || self.next_reconnect.is_some() => return Err(err),
+                Err(err) => {
+                    warn!("VoiceMeeter call failed ({err}), retrying in {}ms...", RETRY_DELAY.as_millis());
+                    thread::sleep(RETRY_DELAY);
+                    attempt += 1;
+                }
             }
         }
     }
 
-    pub fn get_version(&mut self) -> Result<VoicemeeterVersion> {
+    fn reconnect_and_call<T>(&mut self, block: &mut impl FnMut(&mut VoiceMeeterControllerInner) -> Result<T>) -> Result<T> {
+        // Not connected or block call failed, reconnect unless we are still backing off from
+        // previous failed attempts
+        self.check_backoff()?;
+        warn!("VoiceMeeter call failed or VoiceMeeter is not connected, re-connecting now...");
+        let vm = self.connect()?;
+        block(vm)
+    }
+
+    /// Fails while the backoff after failed reconnect attempts isn't over yet
+    fn check_backoff(&self) -> Result<()> {
+        if let Some(next_reconnect) = self.next_reconnect {
+            let now = Instant::now();
+            if now < next_reconnect {
+                return Err(eyre!(
+                    "VoiceMeeter is not connected, next reconnect attempt in {:.1}s",
+                    (next_reconnect - now).as_secs_f32()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Logs in to VoiceMeeter again, backing off further if that fails. Runs the reconnect hook if
+    /// we were connected before.
+    fn connect(&mut self) -> Result<&mut VoiceMeeterControllerInner> {
+        let remote = match VoicemeeterRemote::new() {
+            Ok(remote) => remote,
+            Err(err) => {
+                self.inner = None;
+                self.failed_reconnects += 1;
+                let backoff = RECONNECT_BACKOFF_START
+                    .saturating_mul(2u32.saturating_pow(self.failed_reconnects - 1))
+                    .min(RECONNECT_BACKOFF_MAX);
+                self.next_reconnect = Some(Instant::now() + backoff);
+                return Err(err).wrap_err_with(|| format!(
+                    "failed to connect to VoiceMeeter, retrying in {}s", backoff.as_secs()
+                ));
+            }
+        };
+        self.failed_reconnects = 0;
+        self.next_reconnect = None;
+        if self.connected_before {
+            self.reconnected();
+        }
+        self.connected_before = true;
+        log_version(&remote);
+        // VoiceMeeter may have been restarted, don't trust what we wrote before
+        self.written.clear();
+        Ok(self.inner.insert(VoiceMeeterControllerInner { remote }))
+    }
+
+    /// Checks whether VoiceMeeter is running, connecting through the usual reconnect path if
+    /// necessary. Doesn't log failed attempts since this is called periodically, but keeps to the
+    /// reconnect backoff.
+    pub fn is_running(&mut self) -> bool {
+        if self.inner.is_none() && (self.check_backoff().is_err() || self.connect().is_err()) {
+            return false;
+        }
+        let running = match &self.inner {
+            Some(inner) => inner.remote.get_voicemeeter_type().is_ok(),
+            None => false,
+        };
+        if !running {
+            // Everything has to be written again once VoiceMeeter is back
+            self.written.clear();
+            self.reported_down = true;
+        } else if self.reported_down {
+            // VoiceMeeter may have been updated while it was gone
+            self.query_version();
+            self.reconnected();
+        }
+        running
+    }
+
+    /// Forgets what was written so far, so the next writes go through even if the values look
+    /// unchanged
+    pub fn forget_written(&mut self) {
+        self.written.clear();
+    }
+
+    pub fn get_voicemeeter_type(&mut self) -> Result<VoicemeeterApplication> {
         self.retry_once(|controller| {
-            controller.remote.get_voicemeeter_version()
-                .wrap_err("failed to get VoiceMeeter version")
+            controller.remote.get_voicemeeter_type()
+                .wrap_err("failed to get VoiceMeeter type")
         })
     }
 
     pub fn set_parameter_string(&mut self, param: &str, new_value: &str) -> Result<()> {
-        self.retry_once(|controller| {
+        if self.dry_run {
+            info!("Dry run: would set {param} to {new_value}");
+            return Ok(());
+        }
+        self.retry(self.retries, |controller| {
             controller.remote.set_parameter_string(ParameterNameRef::from_str(param), new_value)
                 .wrap_err_with(|| format!("failed to set string parameter {param} to {new_value}"))
-        })
+        })?;
+        self.count_write();
+        Ok(())
     }
 
     pub fn set_parameter_float(&mut self, param: &str, new_value: f32) -> Result<()> {
-        self.retry_once(|controller| {
+        if self.dry_run {
+            info!("Dry run: would set {param} to {new_value}");
+            return Ok(());
+        }
+        self.retry(self.retries, |controller| {
             controller.remote.set_parameter_float(ParameterNameRef::from_str(param), new_value)
                 .wrap_err_with(|| format!("failed to set float parameter {param} to {new_value}"))
-        })
+        })?;
+        self.count_write();
+        Ok(())
+    }
+
+    /// Same as `set_parameter_float` but skips the write if the parameter already holds this value
+    /// from a previous write, returns whether the parameter was written.
+    pub fn set_parameter_float_if_changed(&mut self, param: &str, new_value: f32) -> Result<bool> {
+        if let Some(old_value) = self.written.get(param) {
+            if nearly_equal(*old_value, new_value, self.float_epsilon) {
+                return Ok(false);
+            }
+        }
+        self.written.remove(param);
+        self.set_parameter_float(param, new_value)?;
+        self.written.insert(param.to_owned(), new_value);
+        Ok(true)
+    }
+
+    /// Same as `set_parameter_float_if_changed` for the gain of a strip or bus (e.g. `Strip[3]`),
+    /// but lets VoiceMeeter fade to the new gain over `fade_ms` instead of jumping to it.
+    pub fn fade_gain_if_changed(&mut self, channel: &str, new_value: f32, fade_ms: u64) -> Result<bool> {
+        let gain_param = format!("{channel}.Gain");
+        if let Some(old_value) = self.written.get(&gain_param) {
+            if nearly_equal(*old_value, new_value, self.float_epsilon) {
+                return Ok(false);
+            }
+        }
+        self.written.remove(&gain_param);
+        self.set_parameter_string(&format!("{channel}.FadeTo"), &format!("({new_value:.1}, {fade_ms})"))?;
+        // The gain ends up there once the fade is done
+        self.written.insert(gain_param, new_value);
+        Ok(true)
+    }
+
+    /// Switches a macro button on or off like clicking it would, running its scripts
+    pub fn set_macro_button(&mut self, button: usize, state: bool) -> Result<()> {
+        if self.dry_run {
+            info!("Dry run: would set macro button {button} to {state}");
+            return Ok(());
+        }
+        self.retry(self.retries, |controller| {
+            controller.remote.set_macrobutton_state(button, state, false)
+                .wrap_err_with(|| format!("failed to set macro button {button} to {state}"))
+        })?;
+        self.count_write();
+        Ok(())
+    }
+
+    /// Same as `set_macro_button` but skips buttons already switched to this state by a previous
+    /// call, returns whether the button was switched.
+    pub fn set_macro_button_if_changed(&mut self, button: usize, state: bool) -> Result<bool> {
+        // Kept along with the parameters so reconnects and resyncs forget it as well
+        let key = format!("MacroButton[{button}]");
+        let value = if state { 1.0 } else { 0.0 };
+        if self.written.get(&key) == Some(&value) {
+            return Ok(false);
+        }
+        self.written.remove(&key);
+        self.set_macro_button(button, state)?;
+        self.written.insert(key, value);
+        Ok(true)
+    }
+
+    /// Records a value that was changed from within VoiceMeeter so it is not written again,
+    /// returns whether it differs from the value we last wrote.
+    pub fn observe_parameter_float(&mut self, param: &str, value: f32) -> bool {
+        let changed = match self.written.get(param) {
+            Some(old_value) => !nearly_equal(*old_value, value, self.float_epsilon),
+            None => true,
+        };
+        self.written.insert(param.to_owned(), value);
+        changed
     }
 
     pub fn get_parameter_string(&mut self, param: &str) -> Result<String> {
@@ -81,10 +389,74 @@ impl VoiceMeeterController {
         })
     }
 
+    /// Same as `get_parameter_float` but never reconnects, returns `None` if VoiceMeeter is not
+    /// connected. Meant for frequent reads that can simply be skipped.
+    pub fn try_get_parameter_float(&self, param: &str) -> Result<Option<f32>> {
+        match &self.inner {
+            Some(controller) => controller.remote.get_parameter_float(ParameterNameRef::from_str(param))
+                .map(Some)
+                .wrap_err_with(|| format!("failed to get float parameter {param}")),
+            None => Ok(None),
+        }
+    }
+
     pub fn update_parameters_dirty(&mut self) -> Result<bool> {
-        self.retry_once(|controller| {
+        // Nothing was written in dry run mode, so there is nothing to flush either
+        if self.dry_run {
+            return Ok(false);
+        }
+        self.retry(self.retries, |controller| {
             controller.remote.is_parameters_dirty()
                 .wrap_err("failed to check update parameters")
         })
     }
+}
+
+impl ParameterSink for VoiceMeeterController {
+    fn set_parameter_float(&mut self, param: &str, new_value: f32) -> Result<()> {
+        VoiceMeeterController::set_parameter_float(self, param, new_value)
+    }
+
+    fn set_parameter_float_if_changed(&mut self, param: &str, new_value: f32) -> Result<bool> {
+        VoiceMeeterController::set_parameter_float_if_changed(self, param, new_value)
+    }
+
+    fn fade_gain_if_changed(&mut self, channel: &str, new_value: f32, fade_ms: u64) -> Result<bool> {
+        VoiceMeeterController::fade_gain_if_changed(self, channel, new_value, fade_ms)
+    }
+
+    fn set_macro_button_if_changed(&mut self, button: usize, state: bool) -> Result<bool> {
+        VoiceMeeterController::set_macro_button_if_changed(self, button, state)
+    }
+
+    fn update_parameters_dirty(&mut self) -> Result<bool> {
+        VoiceMeeterController::update_parameters_dirty(self)
+    }
+
+    fn get_parameter_float(&mut self, param: &str) -> Result<f32> {
+        VoiceMeeterController::get_parameter_float(self, param)
+    }
+}
+
+/// Number of input strips and output buses offered by each VoiceMeeter edition, `None` if unknown
+pub fn channel_counts(application: VoicemeeterApplication) -> Option<(usize, usize)> {
+    match application {
+        VoicemeeterApplication::Voicemeeter => Some((3, 2)),
+        VoicemeeterApplication::VoicemeeterBanana => Some((5, 5)),
+        VoicemeeterApplication::VoicemeeterPotato
+        | VoicemeeterApplication::PotatoX64Bits => Some((8, 8)),
+        _ => None,
+    }
+}
+
+/// Gain range in dB that sounds about the same on each VoiceMeeter edition, used unless a range is
+/// configured. `None` if unknown.
+pub fn default_gain_range(application: VoicemeeterApplication) -> Option<(f32, f32)> {
+    match application {
+        VoicemeeterApplication::Voicemeeter => Some((-30.0, 6.0)),
+        VoicemeeterApplication::VoicemeeterBanana => Some((-30.0, 12.0)),
+        VoicemeeterApplication::VoicemeeterPotato
+        | VoicemeeterApplication::PotatoX64Bits => Some((-36.0, 12.0)),
+        _ => None,
+    }
 }
\ No newline at end of file
@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use crossbeam::channel::Sender;
+use eyre::{Result, WrapErr};
+use crate::ChannelEvent;
+
+/// How often the config file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sends `ChannelEvent::ConfigReload` whenever the config file at `path` is created or changed.
+/// Polls the modification time since editors often replace files instead of writing to them, which
+/// file system notifications don't handle well.
+pub fn spawn(path: PathBuf, send: Sender<ChannelEvent>) -> Result<()> {
+    let mut last_modified = modified(&path);
+    thread::Builder::new()
+        .name("config watcher".to_owned())
+        .spawn(move || loop {
+            thread::sleep(POLL_INTERVAL);
+            let modified = modified(&path);
+            // Editors that replace the file remove it for a moment, it is reloaded once it is back
+            if modified.is_some() && modified != last_modified {
+                last_modified = modified;
+                // Nobody left to reload the config if the channel is gone
+                if send.send(ChannelEvent::ConfigReload(None)).is_err() {
+                    return;
+                }
+            }
+        })
+        .wrap_err("failed to spawn config watcher thread")?;
+    Ok(())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,6 @@
+use std::iter;
+
+/// Converts a string into the null terminated UTF-16 representation expected by Win32 APIs
+pub fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(iter::once(0)).collect()
+}